@@ -0,0 +1,268 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Buf as _;
+use hyper::{Body, Client, StatusCode, Uri};
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use serde::de::DeserializeOwned;
+
+use crate::dto::session_dto::Session;
+use crate::dto::stats_dto::OnlineStats;
+use crate::dto::user_dto::User;
+use crate::error::Error;
+use crate::neos_api_url::NeosApiUrl;
+
+/// how long to wait for a complete upstream response before giving up
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Typed access to the Neos cloud API. Handlers only depend on this trait, so an in-memory fake can stand in for the
+/// real service.
+#[async_trait]
+pub trait NeosApi: Send + Sync {
+    /// every public session currently listed by the API
+    async fn get_sessions(&self) -> Result<Vec<Session>, Error>;
+
+    /// a single user by ID, e.g. `U-runtime`. Fails with [`Error::UserNotFound`] if the user does not exist.
+    async fn get_user(&self, user_id: &str) -> Result<User, Error>;
+
+    /// users whose name matches the given search string
+    async fn search_users(&self, name: &str) -> Result<Vec<User>, Error>;
+
+    /// global online user and session counts
+    async fn get_online_stats(&self) -> Result<OnlineStats, Error>;
+}
+
+/// Production [`NeosApi`] backed by a single connection-pooled hyper client
+pub struct HyperNeosApi {
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+    url: NeosApiUrl,
+    timeout: Duration,
+}
+
+impl HyperNeosApi {
+    pub fn new(url: NeosApiUrl) -> Self {
+        // the https connector also accepts plain http:// URIs
+        let https = HttpsConnector::new();
+        let client = Client::builder().build::<_, Body>(https);
+        HyperNeosApi { client, url, timeout: DEFAULT_TIMEOUT }
+    }
+
+    /// Override how long to wait for each complete upstream response
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, uri: Uri) -> Result<T, Error> {
+        let request = async {
+            let response = self.client.get(uri).await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(Error::UpstreamStatus(status));
+            }
+            Ok(hyper::body::aggregate(response).await?)
+        };
+        let body = tokio::time::timeout(self.timeout, request).await
+            .map_err(|_| Error::Timeout)??;
+        serde_json::from_reader(body.reader())
+            .map_err(Error::Schema)
+    }
+}
+
+#[async_trait]
+impl NeosApi for HyperNeosApi {
+    async fn get_sessions(&self) -> Result<Vec<Session>, Error> {
+        self.get_json(self.url.sessions()).await
+    }
+
+    async fn get_user(&self, user_id: &str) -> Result<User, Error> {
+        match self.get_json(self.url.user(user_id)).await {
+            Err(Error::UpstreamStatus(StatusCode::NOT_FOUND)) => Err(Error::UserNotFound(user_id.to_string())),
+            result => result,
+        }
+    }
+
+    async fn search_users(&self, name: &str) -> Result<Vec<User>, Error> {
+        self.get_json(self.url.user_search(name)).await
+    }
+
+    async fn get_online_stats(&self) -> Result<OnlineStats, Error> {
+        self.get_json(self.url.online_stats()).await
+    }
+}
+
+/// An in-memory [`NeosApi`] for tests. Sessions and users are kept as upstream JSON and parsed on every call, like a
+/// real response.
+#[cfg(test)]
+pub(crate) mod fake {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use serde_json::{json, Value};
+
+    use super::NeosApi;
+    use crate::dto::session_dto::Session;
+    use crate::dto::stats_dto::OnlineStats;
+    use crate::dto::user_dto::User;
+    use crate::error::Error;
+
+    #[derive(Default)]
+    pub(crate) struct FakeNeosApi {
+        pub sessions: Mutex<Vec<Value>>,
+        /// by user ID
+        pub users: Mutex<HashMap<String, Value>>,
+        /// how many times the session list has been fetched
+        pub session_fetches: AtomicUsize,
+        /// how long every call takes
+        pub latency: Mutex<Duration>,
+    }
+
+    impl FakeNeosApi {
+        pub fn new(sessions: Vec<Value>, users: Vec<Value>) -> Self {
+            let users = users.into_iter()
+                .map(|user| (user["id"].as_str().expect("fake users should have an ID").to_string(), user))
+                .collect();
+            FakeNeosApi {
+                sessions: Mutex::new(sessions),
+                users: Mutex::new(users),
+                session_fetches: AtomicUsize::new(0),
+                latency: Mutex::new(Duration::ZERO),
+            }
+        }
+
+        async fn respond(&self) {
+            let latency = *self.latency.lock().unwrap();
+            if !latency.is_zero() {
+                tokio::time::sleep(latency).await;
+            }
+        }
+    }
+
+    #[async_trait]
+    impl NeosApi for FakeNeosApi {
+        async fn get_sessions(&self) -> Result<Vec<Session>, Error> {
+            self.session_fetches.fetch_add(1, Ordering::SeqCst);
+            self.respond().await;
+            let sessions = self.sessions.lock().unwrap().clone();
+            serde_json::from_value(Value::Array(sessions)).map_err(Error::Schema)
+        }
+
+        async fn get_user(&self, user_id: &str) -> Result<User, Error> {
+            self.respond().await;
+            match self.users.lock().unwrap().get(user_id) {
+                Some(user) => serde_json::from_value(user.clone()).map_err(Error::Schema),
+                None => Err(Error::UserNotFound(user_id.to_string())),
+            }
+        }
+
+        async fn search_users(&self, name: &str) -> Result<Vec<User>, Error> {
+            self.respond().await;
+            let users = self.users.lock().unwrap().values()
+                .filter(|user| user["username"].as_str().is_some_and(|username| username.contains(name)))
+                .cloned()
+                .collect();
+            serde_json::from_value(Value::Array(users)).map_err(Error::Schema)
+        }
+
+        async fn get_online_stats(&self) -> Result<OnlineStats, Error> {
+            self.respond().await;
+            let sessions = self.sessions.lock().unwrap();
+            serde_json::from_value(json!({ "publicSessionCount": sessions.len() })).map_err(Error::Schema)
+        }
+    }
+
+    /// A session as upstream lists it. `users` are `(user ID, present)`, and the first one is the host.
+    pub fn session(session_id: &str, name: &str, users: &[(&str, bool)]) -> Value {
+        let (host_user_id, _) = users.first().copied().unwrap_or(("U-nobody", false));
+        let session_users = users.iter()
+            .map(|(user_id, present)| json!({
+                "username": user_id.trim_start_matches("U-"),
+                "userID": user_id,
+                "isPresent": present,
+            }))
+            .collect::<Vec<Value>>();
+        let active_users = users.iter().filter(|(_, present)| *present).count();
+        json!({
+            "name": name,
+            "description": null,
+            "correspondingWorldId": null,
+            "tags": [],
+            "sessionId": session_id,
+            "normalizedSessionId": session_id.to_lowercase(),
+            "hostUserId": host_user_id,
+            "hostMachineId": "machine",
+            "hostUsername": host_user_id.trim_start_matches("U-"),
+            "compatibilityHash": "hash",
+            "universeId": null,
+            "neosVersion": "2022.1.28.1310",
+            "headlessHost": false,
+            "sessionURLs": [],
+            "sessionUsers": session_users,
+            "thumbnail": null,
+            "joinedUsers": users.len(),
+            "activeUsers": active_users,
+            "maxUsers": 16,
+            "mobileFriendly": false,
+            "sessionBeginTime": "2022-01-01T00:00:00Z",
+            "lastUpdate": "2022-01-01T01:00:00Z",
+            "awaySince": null,
+            "accessLevel": "Anyone",
+            "hasEnded": false,
+            "isValid": true,
+        })
+    }
+
+    /// A user as upstream returns them, named after their ID without the `U-` prefix
+    pub fn user(user_id: &str, registration_date: DateTime<Utc>) -> Value {
+        let username = user_id.trim_start_matches("U-");
+        json!({
+            "id": user_id,
+            "username": username,
+            "normalizedUsername": username.to_lowercase(),
+            "registrationDate": registration_date.to_rfc3339(),
+            "isVerified": true,
+            "quotaBytes": 1073741824,
+            "isLocked": false,
+            "usedBytes": 0,
+            "profile": null,
+            "patreonData": null,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::fake::{self, FakeNeosApi};
+    use super::*;
+
+    #[tokio::test]
+    async fn sessions_parse_from_upstream_json() {
+        let neos_api = FakeNeosApi::new(vec![fake::session("S-hub", "Neos Hub", &[("U-zkxs", true), ("U-guest", false)])], vec![]);
+        let sessions = neos_api.get_sessions().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].session_id, "S-hub");
+        assert_eq!(sessions[0].host_user_id.as_deref(), Some("U-zkxs"));
+        assert_eq!(sessions[0].session_users[1].user_id.as_deref(), Some("U-guest"));
+        assert!(!sessions[0].session_users[1].is_present);
+        assert_eq!((sessions[0].active_users, sessions[0].joined_users), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn users_parse_from_upstream_json() {
+        let registered = Utc.ymd(2020, 10, 13).and_hms(19, 41, 20);
+        let neos_api = FakeNeosApi::new(vec![], vec![fake::user("U-zkxs", registered)]);
+        let user = neos_api.get_user("U-zkxs").await.unwrap();
+        assert_eq!(user.username, "zkxs");
+        assert_eq!(user.registration_date, registered);
+        assert!(!user.is_patron());
+        assert_eq!(neos_api.search_users("zk").await.unwrap().len(), 1);
+        assert!(matches!(neos_api.get_user("U-nobody").await, Err(Error::UserNotFound(_))));
+    }
+}
@@ -16,3 +16,26 @@ pub mod iso_8601 {
         s.parse::<DateTime<Utc>>().map_err(serde::de::Error::custom)
     }
 }
+
+pub mod optional_iso_8601 {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        match date {
+            Some(date) => super::iso_8601::serialize(date, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+        where D: Deserializer<'de>
+    {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(s) => s.parse::<DateTime<Utc>>().map(Some).map_err(serde::de::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
@@ -1,3 +1,5 @@
+pub mod response_dto;
 pub mod session_dto;
+pub mod stats_dto;
 pub mod user_dto;
 pub mod custom_serializer;
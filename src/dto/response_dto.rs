@@ -0,0 +1,341 @@
+//! JSON documents returned by our own routes when a client asks for `application/json`
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::kv_store::KvValue;
+use crate::pagination::PageInfo;
+
+#[derive(Serialize)]
+pub struct Greeting {
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct InitTime {
+    pub init_time: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct Counter {
+    pub counter: Option<i64>,
+}
+
+/// A key-value store entry, e.g. `{"namespace":"world1","key":"score","type":"int","value":12}`
+#[derive(Serialize)]
+pub struct KeyValue {
+    pub namespace: String,
+    pub key: String,
+    #[serde(flatten)]
+    pub value: KvValue,
+    #[serde(skip_serializing_if = "Option::is_none", with = "crate::dto::custom_serializer::optional_iso_8601")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct UserRegistration {
+    pub user_id: String,
+    #[serde(with = "crate::dto::custom_serializer::iso_8601")]
+    pub registration_date: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct UserList {
+    pub users: Vec<OnlineUser>,
+    #[serde(flatten)]
+    pub page: PageInfo,
+}
+
+/// a user present in a public session. Unregistered users have no ID.
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct OnlineUser {
+    pub username: String,
+    pub user_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SessionList {
+    pub list: String,
+    /// true if a session appeared since this client's last poll, the same as the `N` prefix of the text format
+    pub new_sessions: bool,
+    /// changes whenever a session is added or removed, or its user count or host presence changes
+    pub version: String,
+    pub sessions: Vec<SessionListEntry>,
+    #[serde(flatten)]
+    pub page: PageInfo,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SessionListEntry {
+    pub session_id: String,
+    pub name: String,
+    pub host_username: String,
+    pub host_user_id: Option<String>,
+    pub active_users: i32,
+    pub joined_users: i32,
+    #[serde(with = "crate::dto::custom_serializer::iso_8601")]
+    pub session_begin_time: DateTime<Utc>,
+    pub uptime_secs: i64,
+    pub host_present: bool,
+    /// when the host left, if they have
+    #[serde(skip_serializing_if = "Option::is_none", with = "crate::dto::custom_serializer::optional_iso_8601")]
+    pub away_since: Option<DateTime<Utc>>,
+    /// the session appeared since this client's last poll, or is being announced by a `session_added` feed event
+    pub is_new: bool,
+    /// absent if the host is unregistered or could not be looked up
+    pub host: Option<SessionHost>,
+    /// why the host could not be looked up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_error: Option<String>,
+    /// only for lists in new-user mode: the recently registered host and present users, youngest account first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_users: Option<Vec<NewUser>>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct NewUser {
+    pub username: String,
+    pub user_id: String,
+    pub is_host: bool,
+    pub account_age_days: i64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SessionHost {
+    #[serde(with = "crate::dto::custom_serializer::iso_8601")]
+    pub registration_date: DateTime<Utc>,
+    pub is_patron: bool,
+}
+
+#[derive(Serialize)]
+pub struct ServerTime {
+    pub unix_ms: i64,
+    #[serde(with = "crate::dto::custom_serializer::iso_8601")]
+    pub iso_8601: DateTime<Utc>,
+}
+
+/// A `/timesync` reply. With the time the client sent its request and the time it received this, the client can work
+/// out the round trip time and its clock offset.
+#[derive(Serialize)]
+pub struct TimeSyncReply {
+    /// whatever the client sent, as JSON if it parses as JSON and as a string otherwise
+    pub client_time: serde_json::Value,
+    pub server_receive_ms: i64,
+    pub server_send_ms: i64,
+}
+
+/// A named timer as of `server_time_ms`, which clients can use to correct for their own clock
+#[derive(Serialize)]
+pub struct TimerState {
+    pub name: String,
+    /// `stopped`, `running` or `paused`
+    pub status: &'static str,
+    pub elapsed_ms: i64,
+    /// time left until the target, never negative; absent without a target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_ms: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_ms: Option<i64>,
+    /// when a running timer with a target will finish
+    #[serde(skip_serializing_if = "Option::is_none", with = "crate::dto::custom_serializer::optional_iso_8601")]
+    pub ends_at: Option<DateTime<Utc>>,
+    pub server_time_ms: i64,
+}
+
+/// A page of a leaderboard, best scores first
+#[derive(Serialize)]
+pub struct Leaderboard {
+    pub board: String,
+    /// `higher` or `lower`: which scores rank first
+    pub order: &'static str,
+    /// `best` or `latest`: which of a player's scores is kept
+    pub keep: &'static str,
+    pub scores: Vec<LeaderboardEntry>,
+    #[serde(flatten)]
+    pub page: PageInfo,
+}
+
+#[derive(Serialize)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub player: String,
+    pub user_id: Option<String>,
+    pub score: f64,
+    #[serde(with = "crate::dto::custom_serializer::iso_8601")]
+    pub submitted_at: DateTime<Utc>,
+}
+
+/// One player's place on a leaderboard
+#[derive(Serialize)]
+pub struct PlayerScore {
+    pub board: String,
+    #[serde(flatten)]
+    pub entry: LeaderboardEntry,
+    /// only for submissions: whether the submitted score was kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated: Option<bool>,
+}
+
+/// The result of removing a whole leaderboard
+#[derive(Serialize)]
+pub struct BoardCleared {
+    pub board: String,
+    pub removed: usize,
+}
+
+/// How full a room is, from `GET /room/{name}`
+#[derive(Serialize)]
+pub struct RoomInfo {
+    pub room: String,
+    pub members: usize,
+    pub max_members: usize,
+    /// how many recent messages would be replayed to a member who joins with `?history=`
+    pub retained: usize,
+}
+
+/// A message on a `/room/{name}` WebSocket
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RoomFeedEvent {
+    /// a message from another member; `replayed` is set for the history sent on joining
+    Message {
+        message: String,
+        #[serde(with = "crate::dto::custom_serializer::iso_8601")]
+        sent_at: DateTime<Utc>,
+        replayed: bool,
+    },
+    /// the room's member count, sent on joining and whenever someone joins or leaves
+    Members {
+        members: usize,
+    },
+    /// this member fell behind and did not receive this many events
+    Missed {
+        count: u64,
+    },
+}
+
+/// A message on the `/sessionfeed` WebSocket
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// the whole list, sent once when the client connects
+    Snapshot {
+        sessions: Vec<SessionListEntry>,
+    },
+    SessionAdded {
+        session: SessionListEntry,
+    },
+    SessionRemoved {
+        session_id: String,
+    },
+    UserCountChanged {
+        session_id: String,
+        active_users: i32,
+        joined_users: i32,
+    },
+    /// the host left a session, whether or not it is still on the list
+    HostLeft {
+        session_id: String,
+    },
+}
+
+impl SessionEvent {
+    /// the `event` field of the JSON form
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionEvent::Snapshot { .. } => "snapshot",
+            SessionEvent::SessionAdded { .. } => "session_added",
+            SessionEvent::SessionRemoved { .. } => "session_removed",
+            SessionEvent::UserCountChanged { .. } => "user_count_changed",
+            SessionEvent::HostLeft { .. } => "host_left",
+        }
+    }
+}
+
+/// Everything `/systemstat` reports. Each section is either its value or `{"error": "..."}` if it is not available on
+/// this platform.
+#[derive(Serialize)]
+pub struct SystemStats {
+    pub mounts: Stat<Vec<Mount>>,
+    pub block_devices: Stat<Vec<String>>,
+    pub networks: Stat<Vec<NetworkInterface>>,
+    pub battery: Stat<Battery>,
+    pub ac_power: Stat<bool>,
+    pub memory: Stat<Memory>,
+    pub load_average: Stat<LoadAverage>,
+    pub uptime_secs: Stat<u64>,
+    /// ISO-8601
+    pub boot_time: Stat<String>,
+    pub cpu_temp: Stat<f32>,
+    pub socket_stats: Stat<SocketStats>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Stat<T> {
+    Value(T),
+    Error { error: String },
+}
+
+impl<T, E: ToString> From<Result<T, E>> for Stat<T> {
+    fn from(result: Result<T, E>) -> Self {
+        match result {
+            Ok(value) => Stat::Value(value),
+            Err(e) => Stat::Error { error: e.to_string() },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Mount {
+    pub mounted_from: String,
+    pub fs_type: String,
+    pub mounted_on: String,
+    pub available_bytes: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub addresses: Vec<String>,
+    pub statistics: Stat<NetworkStatistics>,
+}
+
+#[derive(Serialize)]
+pub struct NetworkStatistics {
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+}
+
+#[derive(Serialize)]
+pub struct Battery {
+    pub remaining_percent: f32,
+    pub remaining_secs: u64,
+}
+
+#[derive(Serialize)]
+pub struct Memory {
+    pub used_bytes: u64,
+    pub total_bytes: u64,
+}
+
+#[derive(Serialize)]
+pub struct LoadAverage {
+    pub one: f32,
+    pub five: f32,
+    pub fifteen: f32,
+}
+
+#[derive(Serialize)]
+pub struct SocketStats {
+    pub tcp_sockets_in_use: usize,
+    pub tcp_sockets_orphaned: usize,
+    pub udp_sockets_in_use: usize,
+    pub tcp6_sockets_in_use: usize,
+    pub udp6_sockets_in_use: usize,
+}
@@ -0,0 +1,26 @@
+use serde::Deserialize;
+
+/// Global online statistics. Upstream occasionally omits counters, so every count defaults to zero.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnlineStats {
+    pub capture_timestamp: Option<String>,
+    #[serde(default)]
+    pub registered_user_count: i32,
+    #[serde(default)]
+    pub instance_count: i32,
+    #[serde(default)]
+    pub vr_user_count: i32,
+    #[serde(default)]
+    pub screen_user_count: i32,
+    #[serde(default)]
+    pub headless_user_count: i32,
+    #[serde(default)]
+    pub mobile_user_count: i32,
+    #[serde(default)]
+    pub public_session_count: i32,
+    #[serde(default)]
+    pub active_public_session_count: i32,
+    #[serde(default)]
+    pub public_world_user_count: i32,
+}
@@ -19,7 +19,7 @@ pub struct User {
 
 impl User {
     pub fn is_patron(&self) -> bool {
-        self.patreon_data.as_ref().map_or(false, |p| p.is_patreon_supporter)
+        self.patreon_data.as_ref().is_some_and(|p| p.is_patreon_supporter)
     }
 
     pub fn abridge(self, cache_time: DateTime<Utc>) -> AbridgedUser {
@@ -0,0 +1,110 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How durations such as session uptime are written in text responses
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum DurationFormat {
+    /// `5:12:07`, hours are not wrapped into days
+    #[default]
+    Hms,
+    /// `5h12m`, the two most significant units
+    Compact,
+    /// `18727`
+    Seconds,
+}
+
+impl DurationFormat {
+    /// Format a number of seconds. Negative durations, e.g. from clock skew, are shown as zero.
+    pub fn format(self, secs: i64) -> String {
+        let secs = secs.max(0);
+        match self {
+            DurationFormat::Hms => format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60),
+            DurationFormat::Compact => {
+                let units = [
+                    (secs / 86400, "d"),
+                    (secs / 3600 % 24, "h"),
+                    (secs / 60 % 60, "m"),
+                    (secs % 60, "s"),
+                ];
+                let first = units.iter().position(|(value, _)| *value > 0).unwrap_or(units.len() - 1);
+                units[first..].iter()
+                    .take(2)
+                    .enumerate()
+                    .filter(|(index, (value, _))| *index == 0 || *value > 0)
+                    .map(|(_, (value, unit))| format!("{}{}", value, unit))
+                    .collect()
+            }
+            DurationFormat::Seconds => secs.to_string(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            DurationFormat::Hms => "hms",
+            DurationFormat::Compact => "compact",
+            DurationFormat::Seconds => "seconds",
+        }
+    }
+}
+
+impl FromStr for DurationFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "hms" => Ok(DurationFormat::Hms),
+            "compact" => Ok(DurationFormat::Compact),
+            "seconds" => Ok(DurationFormat::Seconds),
+            other => Err(format!("unknown duration format \"{}\", expected hms, compact or seconds", other)),
+        }
+    }
+}
+
+impl fmt::Display for DurationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formats(secs: i64) -> [String; 3] {
+        [DurationFormat::Hms.format(secs), DurationFormat::Compact.format(secs), DurationFormat::Seconds.format(secs)]
+    }
+
+    #[test]
+    fn hours_are_not_wrapped() {
+        // uptime used to be printed as minutes and seconds, e.g. 312:07
+        assert_eq!(formats(18727), ["5:12:07", "5h12m", "18727"]);
+        assert_eq!(formats(90061), ["25:01:01", "1d1h", "90061"]);
+    }
+
+    #[test]
+    fn short_durations() {
+        assert_eq!(formats(0), ["0:00:00", "0s", "0"]);
+        assert_eq!(formats(45), ["0:00:45", "45s", "45"]);
+        assert_eq!(formats(61), ["0:01:01", "1m1s", "61"]);
+    }
+
+    #[test]
+    fn compact_skips_zero_second_units() {
+        assert_eq!(DurationFormat::Compact.format(86400), "1d");
+        assert_eq!(DurationFormat::Compact.format(3605), "1h");
+        assert_eq!(DurationFormat::Compact.format(86460), "1d");
+    }
+
+    #[test]
+    fn negative_durations_are_zero() {
+        assert_eq!(formats(-5), ["0:00:00", "0s", "0"]);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for format in [DurationFormat::Hms, DurationFormat::Compact, DurationFormat::Seconds] {
+            assert!(format.to_string().parse::<DurationFormat>() == Ok(format));
+        }
+        assert!("days".parse::<DurationFormat>().is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use std::{fmt, io};
+use std::sync::Arc;
+
+use serde::Serialize;
+use warp::http::{self, Response, StatusCode};
+
+/// Everything that can go wrong while serving a request, talking to the Neos API or persisting local state
+#[derive(Debug)]
+pub enum Error {
+    /// the upstream connection failed or the response body could not be read
+    Network(hyper::Error),
+    /// the upstream did not respond in time
+    Timeout,
+    /// the upstream responded with a non-success status
+    UpstreamStatus(StatusCode),
+    /// the upstream reported that the requested user does not exist
+    UserNotFound(String),
+    /// the upstream response did not match the expected JSON schema
+    Schema(serde_json::Error),
+    /// local state could not be serialized
+    Serialization(serde_json::Error),
+    /// local state could not be read from or written to disk
+    Io(io::Error),
+    /// no session list with this name is configured
+    UnknownSessionList(String),
+    /// the request's query string or headers were not understood; the message explains why
+    InvalidQuery(String),
+    /// a request body could not be used as a value; the message explains why
+    InvalidValue(String),
+    /// no key-value store entry with this `namespace/key`
+    KeyNotFound(String),
+    /// a compare-and-swap found a different value than expected; the message says what it found
+    CompareFailed(String),
+    /// a key-value store operation does not apply to the type of the stored value
+    WrongType(String),
+    /// the named room already has as many members as it allows
+    RoomFull(String),
+    /// no score for this player on this board
+    ScoreNotFound(String),
+    /// a score was submitted with ranking options that differ from the board's; the message gives the board's
+    BoardMismatch(String),
+    /// state the server needs could not be set up, such as a store file that cannot be loaded; the message says which
+    /// and why
+    Startup(String),
+    /// the same error delivered to every caller that shared a coalesced fetch
+    Shared(Arc<Error>),
+}
+
+/// Stable, machine-readable error response body
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream_status: Option<u16>,
+}
+
+impl Error {
+    /// short identifier that clients can match on, which will not change between releases
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Network(_) => "upstream_network_error",
+            Error::Timeout => "upstream_timeout",
+            Error::UpstreamStatus(_) => "upstream_status",
+            Error::UserNotFound(_) => "user_not_found",
+            Error::Schema(_) => "upstream_schema_mismatch",
+            Error::Serialization(_) => "serialization_error",
+            Error::Io(_) => "io_error",
+            Error::UnknownSessionList(_) => "unknown_session_list",
+            Error::InvalidQuery(_) => "invalid_query",
+            Error::InvalidValue(_) => "invalid_value",
+            Error::KeyNotFound(_) => "key_not_found",
+            Error::CompareFailed(_) => "compare_failed",
+            Error::WrongType(_) => "wrong_type",
+            Error::RoomFull(_) => "room_full",
+            Error::ScoreNotFound(_) => "score_not_found",
+            Error::BoardMismatch(_) => "board_mismatch",
+            Error::Startup(_) => "startup_error",
+            Error::Shared(e) => e.code(),
+        }
+    }
+
+    /// status to report to our own clients: 404 only for genuinely missing users, 502/504 for upstream faults
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Network(_) => StatusCode::BAD_GATEWAY,
+            Error::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            Error::UpstreamStatus(_) => StatusCode::BAD_GATEWAY,
+            Error::UserNotFound(_) => StatusCode::NOT_FOUND,
+            Error::Schema(_) => StatusCode::BAD_GATEWAY,
+            Error::Serialization(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::UnknownSessionList(_) => StatusCode::NOT_FOUND,
+            Error::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            Error::InvalidValue(_) => StatusCode::BAD_REQUEST,
+            Error::KeyNotFound(_) => StatusCode::NOT_FOUND,
+            Error::CompareFailed(_) => StatusCode::CONFLICT,
+            Error::WrongType(_) => StatusCode::CONFLICT,
+            Error::RoomFull(_) => StatusCode::CONFLICT,
+            Error::ScoreNotFound(_) => StatusCode::NOT_FOUND,
+            Error::BoardMismatch(_) => StatusCode::CONFLICT,
+            Error::Startup(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Shared(e) => e.status_code(),
+        }
+    }
+
+    /// JSON error response, e.g. `{"error":"user_not_found","message":"user U-foo not found"}`
+    pub fn to_response(&self) -> http::Result<Response<String>> {
+        Response::builder()
+            .status(self.status_code())
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(self.to_json())
+    }
+
+    /// the JSON error response body, for errors reported outside of an HTTP response
+    pub fn to_json(&self) -> String {
+        let upstream_status = match self.unshared() {
+            Error::UpstreamStatus(status) => Some(status.as_u16()),
+            _ => None,
+        };
+        let body = ErrorBody {
+            error: self.code(),
+            message: self.to_string(),
+            upstream_status,
+        };
+        serde_json::to_string(&body).expect("error body should always serialize")
+    }
+
+    fn unshared(&self) -> &Error {
+        match self {
+            Error::Shared(e) => e.unshared(),
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Network(e) => write!(f, "error communicating with the Neos API: {}", e),
+            Error::Timeout => write!(f, "timed out waiting for the Neos API"),
+            Error::UpstreamStatus(status) => write!(f, "the Neos API responded with {}", status),
+            Error::UserNotFound(user_id) => write!(f, "user {} not found", user_id),
+            Error::Schema(e) => write!(f, "unexpected Neos API response: {}", e),
+            Error::Serialization(e) => write!(f, "serialization error: {}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::UnknownSessionList(name) => write!(f, "no session list named \"{}\"", name),
+            Error::InvalidQuery(message) => write!(f, "invalid request: {}", message),
+            Error::InvalidValue(message) => write!(f, "invalid value: {}", message),
+            Error::KeyNotFound(key) => write!(f, "key {} not found", key),
+            Error::CompareFailed(message) => write!(f, "comparison failed: {}", message),
+            Error::WrongType(message) => write!(f, "wrong type: {}", message),
+            Error::RoomFull(room) => write!(f, "room {} is full", room),
+            Error::ScoreNotFound(score) => write!(f, "no score for {}", score),
+            Error::BoardMismatch(message) => write!(f, "board options do not match: {}", message),
+            Error::Startup(message) => write!(f, "{}", message),
+            Error::Shared(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Network(e) => Some(e),
+            Error::Schema(e) => Some(e),
+            Error::Serialization(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Shared(e) => e.source(),
+            Error::Timeout | Error::UpstreamStatus(_) | Error::UserNotFound(_) | Error::UnknownSessionList(_)
+            | Error::InvalidQuery(_) | Error::InvalidValue(_) | Error::KeyNotFound(_) | Error::CompareFailed(_)
+            | Error::WrongType(_) | Error::RoomFull(_) | Error::ScoreNotFound(_) | Error::BoardMismatch(_)
+            | Error::Startup(_) => None,
+        }
+    }
+}
+
+/// lets filters reject a request with an error that the server renders with [`Error::to_response`]
+impl warp::reject::Reject for Error {}
+
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Self {
+        Error::Network(e)
+    }
+}
+
+impl From<Arc<Error>> for Error {
+    /// unwrap a coalesced error if this was the last reference to it
+    fn from(e: Arc<Error>) -> Self {
+        Arc::try_unwrap(e).unwrap_or_else(Error::Shared)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a body whose sender gave up, which is what a dropped upstream connection looks like
+    async fn network_error() -> hyper::Error {
+        let (sender, body) = hyper::Body::channel();
+        sender.abort();
+        hyper::body::to_bytes(body).await.unwrap_err()
+    }
+
+    fn json_error() -> serde_json::Error {
+        serde_json::from_str::<u8>("x").unwrap_err()
+    }
+
+    // stops compiling when a variant is added, as a reminder to add it to the table below
+    fn in_table(error: &Error) {
+        match error {
+            Error::Network(_) | Error::Timeout | Error::UpstreamStatus(_) | Error::UserNotFound(_) | Error::Schema(_)
+            | Error::Serialization(_) | Error::Io(_) | Error::UnknownSessionList(_) | Error::InvalidQuery(_)
+            | Error::InvalidValue(_) | Error::KeyNotFound(_) | Error::CompareFailed(_) | Error::WrongType(_)
+            | Error::RoomFull(_) | Error::ScoreNotFound(_) | Error::BoardMismatch(_) | Error::Startup(_)
+            | Error::Shared(_) => (),
+        }
+    }
+
+    #[tokio::test]
+    async fn codes_and_statuses() {
+        let errors = vec![
+            (Error::Network(network_error().await), StatusCode::BAD_GATEWAY, "upstream_network_error"),
+            (Error::Timeout, StatusCode::GATEWAY_TIMEOUT, "upstream_timeout"),
+            (Error::UpstreamStatus(StatusCode::SERVICE_UNAVAILABLE), StatusCode::BAD_GATEWAY, "upstream_status"),
+            (Error::UserNotFound("U-a".to_string()), StatusCode::NOT_FOUND, "user_not_found"),
+            (Error::Schema(json_error()), StatusCode::BAD_GATEWAY, "upstream_schema_mismatch"),
+            (Error::Serialization(json_error()), StatusCode::INTERNAL_SERVER_ERROR, "serialization_error"),
+            (Error::Io(io::Error::other("disk full")), StatusCode::INTERNAL_SERVER_ERROR, "io_error"),
+            (Error::UnknownSessionList("a".to_string()), StatusCode::NOT_FOUND, "unknown_session_list"),
+            (Error::InvalidQuery("a".to_string()), StatusCode::BAD_REQUEST, "invalid_query"),
+            (Error::InvalidValue("a".to_string()), StatusCode::BAD_REQUEST, "invalid_value"),
+            (Error::KeyNotFound("a/b".to_string()), StatusCode::NOT_FOUND, "key_not_found"),
+            (Error::CompareFailed("a".to_string()), StatusCode::CONFLICT, "compare_failed"),
+            (Error::WrongType("a".to_string()), StatusCode::CONFLICT, "wrong_type"),
+            (Error::RoomFull("a".to_string()), StatusCode::CONFLICT, "room_full"),
+            (Error::ScoreNotFound("a".to_string()), StatusCode::NOT_FOUND, "score_not_found"),
+            (Error::BoardMismatch("a".to_string()), StatusCode::CONFLICT, "board_mismatch"),
+            (Error::Startup("a".to_string()), StatusCode::INTERNAL_SERVER_ERROR, "startup_error"),
+            (Error::Shared(Arc::new(Error::Timeout)), StatusCode::GATEWAY_TIMEOUT, "upstream_timeout"),
+        ];
+        for (error, status, code) in errors {
+            in_table(&error);
+            assert_eq!(error.status_code(), status, "status of {}", error);
+            assert_eq!(error.code(), code, "code of {}", error);
+            let response = error.to_response().unwrap();
+            assert_eq!(response.status(), status);
+            let body = serde_json::from_str::<serde_json::Value>(response.body()).unwrap();
+            assert_eq!(body["error"], code);
+            assert_eq!(body["message"], error.to_string());
+        }
+    }
+
+    #[test]
+    fn upstream_status_is_reported_through_sharing() {
+        let shared = Error::Shared(Arc::new(Error::UpstreamStatus(StatusCode::TOO_MANY_REQUESTS)));
+        assert_eq!(shared.to_json(), "{\"error\":\"upstream_status\",\"message\":\"the Neos API responded with 429 Too Many Requests\",\"upstream_status\":429}");
+        assert_eq!(Error::Timeout.to_json(), "{\"error\":\"upstream_timeout\",\"message\":\"timed out waiting for the Neos API\"}");
+    }
+}
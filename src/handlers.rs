@@ -0,0 +1,1524 @@
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc, SecondsFormat};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use systemstat::{self, Platform};
+use tokio::sync::{broadcast, watch};
+use warp::http::{self, Response, StatusCode};
+use warp::hyper::body::Bytes;
+use warp::filters::sse;
+use warp::Reply;
+
+use crate::client::NeosApi;
+use crate::dto::response_dto::{self, BoardCleared, Counter, Greeting, InitTime, KeyValue, Leaderboard, LeaderboardEntry, OnlineUser, PlayerScore, SessionEvent, SessionHost, ServerTime, RoomFeedEvent, RoomInfo, SessionListEntry, SystemStats, TimeSyncReply, TimerState, UserList, UserRegistration};
+use crate::dto::session_dto::Session;
+use crate::duration_format::DurationFormat;
+use crate::error::Error;
+use crate::kv_store::{KvEntry, KvStore, KvValue, ValueType, COUNTER_KEY, INIT_TIME_KEY, LEGACY_NAMESPACE};
+use crate::leaderboard::{KeepPolicy, Leaderboards, PlayerKey, RankedScore, Score, ScoreOrder};
+use crate::notification::NotificationTracker;
+use crate::output_format::OutputFormat;
+use crate::pagination::{Page, PageInfo};
+use crate::rich_text::ColorRules;
+use crate::rooms::{Membership, RoomEvent, RoomMessage, Rooms};
+use crate::sanitize::SanitizePolicy;
+use crate::session_feed;
+use crate::session_list::{self, SessionFilter, SessionListDefinition, SessionLists, SessionSort, SortKey};
+use crate::session_poller::{SessionPoller, SessionSnapshot};
+use crate::template::{Field, Template};
+use crate::timers::{self, TargetChange, Timer, TimerOperation, Timers};
+use crate::user_cache::UserCache;
+
+pub(crate) type NotificationDb = Arc<NotificationTracker>;
+pub(crate) type UserCacheDb = Arc<UserCache>;
+pub(crate) type NeosApiDb = Arc<dyn NeosApi>;
+pub(crate) type SessionPollerDb = Arc<SessionPoller>;
+pub(crate) type SessionListsDb = Arc<SessionLists>;
+pub(crate) type KvStoreDb = Arc<KvStore>;
+pub(crate) type TimersDb = Arc<Timers>;
+pub(crate) type RoomsDb = Arc<Rooms>;
+pub(crate) type LeaderboardsDb = Arc<Leaderboards>;
+
+pub(crate) async fn user_registration_handler(user_id: String, format: OutputFormat, neos_api: NeosApiDb, user_cache: UserCacheDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = match user_cache.lookup(neos_api.as_ref(), user_id.clone()).await {
+        Ok(user) => user,
+        Err(e) => return Ok(e.to_response())
+    };
+    let registration = UserRegistration {
+        user_id,
+        registration_date: user.registration_date,
+    };
+    Ok(render(Response::builder().status(StatusCode::OK), format, &registration, |registration| {
+        registration.registration_date.to_rfc3339_opts(SecondsFormat::Secs, true)
+    }))
+}
+
+pub(crate) async fn userlist_handler(format: OutputFormat, page: Page, sanitize: SanitizePolicy, session_poller: SessionPollerDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let snapshot = match session_poller.snapshot().await {
+        Ok(snapshot) => snapshot,
+        Err(e) => return Ok(e.to_response())
+    };
+    let mut users = snapshot.sessions.iter()
+        .flat_map(|s| s.session_users.iter())
+        .map(|u| OnlineUser {
+            username: u.username.clone(),
+            user_id: u.user_id.clone(),
+        }).collect::<Vec<OnlineUser>>();
+    users.sort_unstable();
+    users.dedup();
+    let response = Response::builder().status(StatusCode::OK).header(http::header::AGE, snapshot_age(&snapshot));
+    match format {
+        // lines sort differently from users and sanitizing can make two of them equal, so page the finished lines
+        OutputFormat::Text => {
+            let mut lines = users.iter()
+                .map(|u| {
+                    if u.user_id.is_some() {
+                        sanitize.sanitize(&u.username)
+                    } else {
+                        format!("?{}", sanitize.sanitize(&u.username))
+                    }
+                }).collect::<Vec<String>>();
+            lines.sort_unstable();
+            lines.dedup();
+            let (mut lines, page_info) = page.apply(lines);
+            if page.footer {
+                lines.push(page_info.footer());
+            }
+            Ok(page_headers(response, &page_info).body(lines.join("\n")))
+        }
+        OutputFormat::Json => {
+            let (users, page_info) = page.apply(users);
+            Ok(json_body(page_headers(response, &page_info), &UserList { users, page: page_info }))
+        }
+    }
+}
+
+/// clients that do not identify themselves share this notification state
+const ANONYMOUS_CLIENT: &str = "";
+
+#[derive(Deserialize)]
+pub(crate) struct SessionListQuery {
+    /// identifies the polling client, so each one gets its own `N`/`X` notification state for each filter it uses
+    client: Option<String>,
+    min_users: Option<String>,
+    max_users: Option<String>,
+    min_host_age_days: Option<String>,
+    max_host_age_days: Option<String>,
+    patron: Option<String>,
+    headless: Option<String>,
+    mobile_friendly: Option<String>,
+    /// comma separated
+    access_level: Option<String>,
+    /// comma separated
+    tags: Option<String>,
+    neos_version: Option<String>,
+    sort: Option<String>,
+    order: Option<String>,
+    /// name of a configured line template
+    template: Option<String>,
+    /// style lines with the color rules
+    rich_text: Option<String>,
+    /// long poll: hold the response until the list's version differs from this one
+    version: Option<String>,
+    /// long poll: the most seconds to hold the response for
+    wait: Option<String>,
+}
+
+impl SessionListQuery {
+    fn select(&self, list_name: &str, session_lists: &SessionLists, sanitize: SanitizePolicy) -> Result<ListSelection, Error> {
+        let list = session_lists.get(list_name).ok_or_else(|| Error::UnknownSessionList(list_name.to_string()))?;
+        let filter = self.filter()?;
+        let sort = self.sort()?;
+        let template_name = self.template.as_deref().unwrap_or(&list.template);
+        let template = session_lists.template(template_name)
+            .ok_or_else(|| Error::InvalidQuery(format!("unknown template \"{}\"", template_name)))?;
+        let rich_text = parse_param("rich_text", &self.rich_text)?.unwrap_or(list.rich_text);
+        // names must not be able to smuggle in their own tags, or the start of one, when we are emitting rich text
+        let sanitize = if rich_text {
+            SanitizePolicy { strip_rich_text: true, escape_rich_text: true, ..sanitize }
+        } else {
+            sanitize
+        };
+        Ok(ListSelection {
+            list: list.clone(),
+            filter,
+            sort,
+            template,
+            rich_text,
+            sanitize,
+        })
+    }
+
+    fn filter(&self) -> Result<SessionFilter, Error> {
+        let filter = SessionFilter {
+            min_active_users: parse_param("min_users", &self.min_users)?,
+            max_active_users: parse_param("max_users", &self.max_users)?,
+            min_host_age_days: parse_param("min_host_age_days", &self.min_host_age_days)?,
+            max_host_age_days: parse_param("max_host_age_days", &self.max_host_age_days)?,
+            patron: parse_param("patron", &self.patron)?,
+            headless: parse_param("headless", &self.headless)?,
+            mobile_friendly: parse_param("mobile_friendly", &self.mobile_friendly)?,
+            access_levels: split_param(&self.access_level),
+            tags: split_param(&self.tags),
+            neos_version: self.neos_version.clone(),
+        };
+        check_range("min_users", filter.min_active_users, "max_users", filter.max_active_users)?;
+        check_range("min_host_age_days", filter.min_host_age_days, "max_host_age_days", filter.max_host_age_days)?;
+        Ok(filter)
+    }
+
+    fn sort(&self) -> Result<SessionSort, Error> {
+        let key = match self.sort.as_deref() {
+            Some(key) => SortKey::parse(key).ok_or_else(|| Error::InvalidQuery(format!(
+                "invalid value \"{}\" for sort, expected one of uptime, users, host_registration, name", key
+            )))?,
+            None => SortKey::Uptime,
+        };
+        let descending = match self.order.as_deref() {
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(order) => return Err(Error::InvalidQuery(format!("invalid value \"{}\" for order, expected asc or desc", order))),
+            None => key.default_descending(),
+        };
+        Ok(SessionSort { key, descending })
+    }
+
+    // the version to wait for a change from, and how long to wait, if this is a long poll
+    fn long_poll(&self) -> Result<Option<(String, Duration)>, Error> {
+        let wait = parse_param::<u64>("wait", &self.wait)?;
+        if wait.is_some_and(|wait| wait > session_feed::MAX_LONG_POLL_WAIT.as_secs()) {
+            return Err(Error::InvalidQuery(format!("wait must be at most {} seconds", session_feed::MAX_LONG_POLL_WAIT.as_secs())));
+        }
+        match (self.version.clone(), wait) {
+            (Some(version), wait) => Ok(Some((version, wait.map_or(session_feed::DEFAULT_LONG_POLL_WAIT, Duration::from_secs)))),
+            (None, Some(_)) => Err(Error::InvalidQuery("wait requires version".to_string())),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+// parse an optional query parameter, explaining what was wrong with it
+fn parse_param<T: FromStr>(name: &str, value: &Option<String>) -> Result<Option<T>, Error>
+    where T::Err: fmt::Display
+{
+    value.as_deref()
+        .map(|value| value.parse::<T>().map_err(|e| Error::InvalidQuery(format!("invalid value \"{}\" for {}: {}", value, name, e))))
+        .transpose()
+}
+
+// comma separated query parameter
+fn split_param(value: &Option<String>) -> Vec<String> {
+    value.iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn check_range<T: PartialOrd + fmt::Display>(min_name: &str, min: Option<T>, max_name: &str, max: Option<T>) -> Result<(), Error> {
+    match (min, max) {
+        (Some(min), Some(max)) if min > max => Err(Error::InvalidQuery(format!("{} ({}) is greater than {} ({})", min_name, min, max_name, max))),
+        _ => Ok(()),
+    }
+}
+
+// everything a session list request selects, checked before any upstream work is done
+pub(crate) struct ListSelection {
+    list: SessionListDefinition,
+    filter: SessionFilter,
+    sort: SessionSort,
+    template: Arc<Template>,
+    rich_text: bool,
+    sanitize: SanitizePolicy,
+}
+
+impl ListSelection {
+    /// The sorted entries of this list in a snapshot. Every host (and in new-user mode, every present user) is
+    /// resolved up front, in parallel.
+    async fn entries(&self, snapshot: &SessionSnapshot, neos_api: &dyn NeosApi, user_cache: &UserCache) -> Vec<SessionListEntry> {
+        let sessions = snapshot.sessions.iter()
+            .filter(|s| self.list.matches(s) && self.filter.matches_session(s))
+            .collect::<Vec<&Session>>();
+
+        let user_ids = sessions.iter().flat_map(|session| self.list.users_to_look_up(session));
+        let users = user_cache.lookup_many(neos_api, user_ids).await;
+
+        let current_time = Utc::now();
+        let mut entries = Vec::with_capacity(sessions.len());
+        for session in sessions.into_iter() {
+            let host = session.host_user_id.as_ref().and_then(|user_id| users.get(user_id)).and_then(|host| host.as_ref().ok());
+            if !self.filter.matches_host(host, current_time) {
+                continue;
+            }
+            let new_users = self.list.new_users(session, &users, current_time);
+            if new_users.as_ref().is_some_and(|new_users| new_users.is_empty()) {
+                continue;
+            }
+            let session_start_time = session.session_begin_time.parse::<DateTime<Utc>>().unwrap_or(Utc.timestamp_millis(0));
+            let uptime = current_time.signed_duration_since(session_start_time);
+            let (host, host_error) = match session.host_user_id.as_ref().and_then(|user_id| users.get(user_id)) {
+                Some(Ok(user)) => (Some(SessionHost {
+                    registration_date: user.registration_date,
+                    is_patron: user.is_patron,
+                }), None),
+                Some(Err(err)) => (None, Some(err.to_string())),
+                None => (None, None),
+            };
+            entries.push(SessionListEntry {
+                session_id: session.session_id.clone(),
+                name: session.name.clone(),
+                host_username: session.host_username.clone(),
+                host_user_id: session.host_user_id.clone(),
+                active_users: session.active_users,
+                joined_users: session.joined_users,
+                session_begin_time: session_start_time,
+                uptime_secs: uptime.num_seconds(),
+                host_present: session_list::host_present(session),
+                away_since: session.away_since.as_ref().and_then(|away_since| away_since.parse::<DateTime<Utc>>().ok()),
+                // what is new depends on the route: /sessionlist marks sessions the client has not been shown before,
+                // and the session feeds mark the sessions in session_added events
+                is_new: false,
+                host,
+                host_error,
+                new_users,
+            });
+        }
+
+        self.sort.sort(&mut entries);
+        entries
+    }
+
+    fn color_rules<'a>(&self, session_lists: &'a SessionLists) -> Option<&'a ColorRules> {
+        if self.rich_text {
+            Some(&session_lists.color_rules)
+        } else {
+            None
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn sessionlist_handler(list_name: String, format: OutputFormat, page: Page, sanitize: SanitizePolicy, uptime_format: DurationFormat, query: SessionListQuery, client_header: Option<String>, neos_api: NeosApiDb, session_poller: SessionPollerDb, session_lists: SessionListsDb, notifications: NotificationDb, user_cache: UserCacheDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let (selection, long_poll) = match query.select(&list_name, &session_lists, sanitize).and_then(|selection| Ok((selection, query.long_poll()?))) {
+        Ok(request) => request,
+        Err(e) => return Ok(e.to_response())
+    };
+    // subscribe before taking the snapshot, so a long poll cannot miss a snapshot fetched in between
+    let mut updates = session_poller.subscribe();
+    let mut snapshot = match session_poller.snapshot().await {
+        Ok(snapshot) => snapshot,
+        Err(e) => return Ok(e.to_response())
+    };
+
+    let mut entries = selection.entries(&snapshot, neos_api.as_ref(), &user_cache).await;
+    let mut version = session_feed::version(&entries);
+
+    // hold the response until the list no longer matches the client's version, or until the wait is over
+    if let Some((client_version, wait)) = long_poll {
+        let deadline = tokio::time::Instant::now() + wait;
+        while version == client_version {
+            if !matches!(tokio::time::timeout_at(deadline, updates.changed()).await, Ok(Ok(()))) {
+                break;
+            }
+            let latest = match updates.borrow().clone() {
+                Some(latest) if !Arc::ptr_eq(&latest, &snapshot) => latest,
+                _ => continue,
+            };
+            entries = selection.entries(&latest, neos_api.as_ref(), &user_cache).await;
+            version = session_feed::version(&entries);
+            snapshot = latest;
+        }
+    }
+
+    let shown_set = entries.iter()
+        .map(|entry| entry.session_id.clone())
+        .collect::<HashSet<String>>();
+
+    // the query parameter wins over the header, since it is easier to change from Logix
+    let client = query.client.or(client_header);
+    // the filter changes which sessions are shown, so each filtered view keeps its own notification state
+    let view = format!("{} {}", list_name, selection.filter.key());
+    let new_set = notifications.update(&view, client.as_deref().unwrap_or(ANONYMOUS_CLIENT), shown_set);
+    for entry in entries.iter_mut() {
+        entry.is_new = new_set.contains(&entry.session_id);
+    }
+
+    // the notification covers every page, so it is decided before paging
+    let (entries, page_info) = page.apply(entries);
+
+    let session_list = response_dto::SessionList {
+        list: list_name,
+        new_sessions: !new_set.is_empty(),
+        version,
+        sessions: entries,
+        page: page_info,
+    };
+    let response = page_headers(Response::builder().status(StatusCode::OK).header(http::header::AGE, snapshot_age(&snapshot)), &page_info)
+        .header("x-list-version", &session_list.version);
+    Ok(render(response, format, &session_list, |session_list| {
+        let text = session_list_text(session_list, &selection, uptime_format, selection.color_rules(&session_lists));
+        // the version gets a line of its own, so the footer reads the same as on every other list route
+        if page.footer {
+            format!("{}\n{}\nversion={}", text, session_list.page.footer(), session_list.version)
+        } else {
+            text
+        }
+    }))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn sessionfeed_handler(list_name: String, websocket: warp::ws::Ws, format: OutputFormat, sanitize: SanitizePolicy, uptime_format: DurationFormat, query: SessionListQuery, neos_api: NeosApiDb, session_poller: SessionPollerDb, session_lists: SessionListsDb, user_cache: UserCacheDb) -> warp::reply::Response {
+    // reject bad parameters with an ordinary error response, before upgrading
+    let selection = match query.select(&list_name, &session_lists, sanitize) {
+        Ok(selection) => selection,
+        Err(e) => return e.to_response().into_response()
+    };
+    websocket.on_upgrade(move |websocket| async move {
+        let feed = SessionFeed {
+            watcher: ListWatcher::new(selection, neos_api, session_poller, user_cache),
+            format,
+            uptime_format,
+            session_lists,
+        };
+        if let Err(e) = feed.run(websocket).await {
+            eprintln!("/sessionfeed/{}: {}", list_name, e);
+        }
+    }).into_response()
+}
+
+pub(crate) fn sessionevents_handler(list_name: String, query: SessionListQuery, neos_api: NeosApiDb, session_poller: SessionPollerDb, session_lists: SessionListsDb, user_cache: UserCacheDb) -> warp::reply::Response {
+    // events are always JSON, which is never sanitized
+    let selection = match query.select(&list_name, &session_lists, SanitizePolicy::none()) {
+        Ok(selection) => selection,
+        Err(e) => return e.to_response().into_response()
+    };
+    let watcher = ListWatcher::new(selection, neos_api, session_poller, user_cache);
+    // each server-sent event is named after its event type and carries the same JSON as the WebSocket feed
+    let events = futures::stream::unfold(Some(watcher), |watcher| async move {
+        let mut watcher = watcher?;
+        match watcher.next().await {
+            Ok(events) => {
+                let events = events.iter()
+                    .map(|event| match serde_json::to_string(event) {
+                        Ok(json) => sse::Event::default().event(event.name()).data(json),
+                        Err(e) => sse::Event::default().event("error").data(Error::Serialization(e).to_json()),
+                    })
+                    .collect::<Vec<sse::Event>>();
+                Some((events, Some(watcher)))
+            }
+            // report the failure, then end the stream
+            Err(e) => Some((vec![sse::Event::default().event("error").data(e.to_json())], None)),
+        }
+    }).flat_map(|events| futures::stream::iter(events.into_iter().map(Ok::<sse::Event, Infallible>)));
+    sse::reply(sse::keep_alive().stream(events)).into_response()
+}
+
+// follows one session list from snapshot to snapshot, for the streaming routes
+struct ListWatcher {
+    selection: ListSelection,
+    neos_api: NeosApiDb,
+    session_poller: SessionPollerDb,
+    user_cache: UserCacheDb,
+    updates: watch::Receiver<Option<Arc<SessionSnapshot>>>,
+    // the snapshot the client was last told about, and its entries
+    current: Option<(Arc<SessionSnapshot>, Vec<SessionListEntry>)>,
+    // a newer snapshot that has been noticed but not yet compared
+    pending: Option<Arc<SessionSnapshot>>,
+}
+
+impl ListWatcher {
+    fn new(selection: ListSelection, neos_api: NeosApiDb, session_poller: SessionPollerDb, user_cache: UserCacheDb) -> Self {
+        // subscribe first, so no snapshot fetched while the first one is being sent is missed
+        let updates = session_poller.subscribe();
+        ListWatcher {
+            selection,
+            neos_api,
+            session_poller,
+            user_cache,
+            updates,
+            current: None,
+            pending: None,
+        }
+    }
+
+    /// The whole list on the first call, then the events from the next snapshot that changes it. Dropping the future
+    /// part way through loses no snapshot, but the lookups for it start over on the next call.
+    async fn next(&mut self) -> Result<Vec<SessionEvent>, Error> {
+        if self.current.is_none() {
+            let snapshot = self.session_poller.snapshot().await?;
+            let entries = self.selection.entries(&snapshot, self.neos_api.as_ref(), &self.user_cache).await;
+            let event = SessionEvent::Snapshot { sessions: entries.clone() };
+            self.current = Some((snapshot, entries));
+            return Ok(vec![event]);
+        }
+        loop {
+            let latest = match self.pending.clone() {
+                Some(latest) => latest,
+                None => {
+                    self.updates.changed().await.expect("the session poller should outlive its subscribers");
+                    let current = self.current.as_ref().map(|(snapshot, _)| snapshot);
+                    self.pending = self.updates.borrow().clone()
+                        .filter(|latest| current.is_none_or(|current| !Arc::ptr_eq(latest, current)));
+                    continue;
+                }
+            };
+            let entries = self.selection.entries(&latest, self.neos_api.as_ref(), &self.user_cache).await;
+            let previous = self.current.replace((latest.clone(), entries)).map(|(_, entries)| entries).unwrap_or_default();
+            self.pending = None;
+            let current = self.current.as_ref().map(|(_, entries)| entries.as_slice()).unwrap_or_default();
+            let events = session_feed::diff(&previous, current, &latest);
+            if !events.is_empty() {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+// one connected session feed client
+struct SessionFeed {
+    watcher: ListWatcher,
+    format: OutputFormat,
+    uptime_format: DurationFormat,
+    session_lists: SessionListsDb,
+}
+
+impl SessionFeed {
+    // send the current list, then the changes between each new snapshot and the one before it until the client leaves
+    async fn run(mut self, websocket: warp::ws::WebSocket) -> Result<(), warp::Error> {
+        let (mut tx, mut rx) = websocket.split();
+        // Client messages are read in their own task, which ends when the client leaves. Waking up for each of them
+        // here would cancel the lookups for the next list, and a client pinging faster than those take would wait forever.
+        let mut client_gone = tokio::spawn(async move {
+            while let Some(Ok(message)) = rx.next().await {
+                if message.is_close() {
+                    break;
+                }
+            }
+        });
+        let result = async {
+            loop {
+                let events = tokio::select! {
+                    _ = &mut client_gone => return Ok(()),
+                    events = self.watcher.next() => events,
+                };
+                match events {
+                    Ok(events) => for event in events.iter() {
+                        tx.send(self.message(event)).await?;
+                    },
+                    Err(e) => {
+                        tx.send(warp::ws::Message::text(self.error_message(&e))).await?;
+                        return tx.close().await;
+                    }
+                }
+            }
+        }.await;
+        client_gone.abort();
+        result
+    }
+
+    fn message(&self, event: &SessionEvent) -> warp::ws::Message {
+        let text = match self.format {
+            OutputFormat::Text => self.event_text(event),
+            OutputFormat::Json => match serde_json::to_string(event) {
+                Ok(json) => json,
+                Err(e) => return warp::ws::Message::text(self.error_message(&Error::Serialization(e))),
+            },
+        };
+        warp::ws::Message::text(text)
+    }
+
+    // `<event> <session id> <details>`, with the whole list in the same line format as /sessionlist for snapshots
+    fn event_text(&self, event: &SessionEvent) -> String {
+        let color_rules = self.watcher.selection.color_rules(&self.session_lists);
+        let current_time = Utc::now();
+        match event {
+            SessionEvent::Snapshot { sessions } => {
+                let lines = sessions.iter()
+                    .map(|entry| session_line(entry, &self.watcher.selection, self.uptime_format, color_rules, current_time))
+                    .collect::<Vec<String>>();
+                format!("snapshot {}\n{}", sessions.len(), lines.join("\n")).trim_end().to_string()
+            }
+            SessionEvent::SessionAdded { session } => format!(
+                "session_added {} {}", session.session_id, session_line(session, &self.watcher.selection, self.uptime_format, color_rules, current_time)
+            ),
+            SessionEvent::SessionRemoved { session_id } => format!("session_removed {}", session_id),
+            SessionEvent::UserCountChanged { session_id, active_users, joined_users } => format!(
+                "user_count_changed {} {}/{}", session_id, active_users, joined_users
+            ),
+            SessionEvent::HostLeft { session_id } => format!("host_left {}", session_id),
+        }
+    }
+
+    fn error_message(&self, error: &Error) -> String {
+        match self.format {
+            OutputFormat::Text => format!("error {}", error),
+            OutputFormat::Json => error.to_json(),
+        }
+    }
+}
+
+// report the size of the full list on paged routes, so clients know whether to fetch another page
+fn page_headers(response: http::response::Builder, page_info: &PageInfo) -> http::response::Builder {
+    response
+        .header("x-total-count", page_info.total)
+        .header("x-has-more", page_info.has_more.to_string())
+}
+
+// the Logix-friendly format: an N/X notification prefix followed by one templated line per session
+fn session_list_text(session_list: &response_dto::SessionList, selection: &ListSelection, uptime_format: DurationFormat, color_rules: Option<&ColorRules>) -> String {
+    let prefix_string = if session_list.new_sessions {
+        "N"
+    } else {
+        "X"
+    };
+    let current_time = Utc::now();
+    let session_list_string = session_list.sessions.iter()
+        .map(|entry| session_line(entry, selection, uptime_format, color_rules, current_time))
+        .collect::<Vec<String>>()
+        .join("\n");
+    format!("{}{}", prefix_string, session_list_string)
+}
+
+fn session_line(entry: &SessionListEntry, selection: &ListSelection, uptime_format: DurationFormat, color_rules: Option<&ColorRules>, current_time: DateTime<Utc>) -> String {
+    let sanitize = selection.sanitize;
+    // in new-user mode, the youngest account that put this session on the list
+    let new_user = entry.new_users.as_ref().and_then(|new_users| new_users.first());
+    let line = selection.template.render(|field| match field {
+        Field::SessionId => Some(entry.session_id.clone()),
+        Field::Host => Some(sanitize.sanitize(&entry.host_username)),
+        Field::World => Some(sanitize.sanitize(&entry.name)),
+        Field::Active => Some(entry.active_users.to_string()),
+        Field::Joined => Some(entry.joined_users.to_string()),
+        Field::Uptime => Some(uptime_format.format(entry.uptime_secs)),
+        Field::UptimeSecs => Some(entry.uptime_secs.to_string()),
+        Field::Registered => entry.host.as_ref().map(|host| format_registration_date(&host.registration_date)),
+        Field::Patron => entry.host.as_ref().filter(|host| host.is_patron).map(|_| "patron".to_string()),
+        Field::AgeDays => entry.host.as_ref().map(|host| current_time.signed_duration_since(host.registration_date).num_days().to_string()),
+        Field::HostError => entry.host_error.as_ref().map(|err| sanitize.sanitize(err)),
+        Field::NewUser => new_user.map(|new_user| sanitize.sanitize(&new_user.username)),
+        Field::NewUserAgeDays => new_user.map(|new_user| new_user.account_age_days.to_string()),
+    });
+    match color_rules {
+        Some(color_rules) => color_rules.apply(line, entry, current_time),
+        None => line,
+    }
+}
+
+// render a response document as Logix-friendly text or as JSON
+fn render<T: Serialize>(response: http::response::Builder, format: OutputFormat, document: &T, text: impl FnOnce(&T) -> String) -> http::Result<Response<String>> {
+    match format {
+        OutputFormat::Text => response.body(text(document)),
+        OutputFormat::Json => json_body(response, document),
+    }
+}
+
+fn json_body<T: Serialize>(response: http::response::Builder, document: &T) -> http::Result<Response<String>> {
+    match serde_json::to_string(document) {
+        Ok(json) => response.header(http::header::CONTENT_TYPE, "application/json").body(json),
+        Err(e) => Error::Serialization(e).to_response(),
+    }
+}
+
+// value for the Age header: how many whole seconds ago the snapshot was fetched from upstream
+fn snapshot_age(snapshot: &SessionSnapshot) -> String {
+    snapshot.age().as_secs().to_string()
+}
+
+// wshello handler
+pub(crate) async fn wshello_handler(websocket: warp::ws::WebSocket) {
+    println!("/wshello: handler called");
+
+    let (mut tx, mut rx) = websocket.split();
+
+    println!("/wshello: connected");
+
+    while let Some(result) = rx.next().await {
+        let message = match result {
+            Ok(message) => {
+                println!("/wshello: received {:?}", message);
+                message
+            }
+            Err(e) => {
+                eprintln!("/wshello: message error: {:?}", e);
+                break;
+            }
+        };
+        let message = match message.to_str() {
+            Ok(str) => str,
+            Err(e) => {
+                eprintln!("/wshello: error converting message to string: {:?}", e);
+                continue;
+            }
+        };
+        let message = format!("Hello, {}!", message);
+        match tx.send(warp::ws::Message::text(message)).await {
+            Ok(e) => println!("/wshello: sending message: {:?}", e),
+            Err(e) => eprintln!("/wshello: error sending message: {:?}", e),
+        };
+    }
+    println!("/wshello: disconnected");
+}
+
+// GET /hello/{name} handler
+pub(crate) fn hello_handler(name: String, format: OutputFormat, sanitize: SanitizePolicy) -> http::Result<Response<String>> {
+    let greeting = Greeting { message: format!("Hello, {}!", name) };
+    render(Response::builder().status(StatusCode::OK), format, &greeting, |_| format!("Hello, {}!", sanitize.sanitize(&name)))
+}
+
+// GET /hello handler
+pub(crate) fn hello_fallback_handler(format: OutputFormat) -> http::Result<Response<String>> {
+    let greeting = Greeting { message: "Hello!".to_string() };
+    render(Response::builder().status(StatusCode::OK), format, &greeting, |greeting| greeting.message.clone())
+}
+
+// normal init_time route handler: the first value posted is kept
+pub(crate) async fn init_time_handler(format: OutputFormat, kv_store: KvStoreDb, bytes: Bytes) -> Result<http::Result<Response<String>>, warp::Rejection> {
+    let init_time = async {
+        let init_time = bytes_to_i64(bytes)?;
+        legacy_int(INIT_TIME_KEY, kv_store.set_if_absent(LEGACY_NAMESPACE, INIT_TIME_KEY, KvValue::Int(init_time), None).await?)
+    }.await;
+    Ok(match init_time {
+        Ok(init_time) => render_init_time(format, Some(init_time), init_time.to_string()),
+        Err(e) => e.to_response(),
+    })
+}
+
+// handler to reset the stored init_time
+pub(crate) async fn init_time_reset_handler(format: OutputFormat, kv_store: KvStoreDb) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(match kv_store.delete(LEGACY_NAMESPACE, INIT_TIME_KEY).await {
+        Ok(_) | Err(Error::KeyNotFound(_)) => render_init_time(format, None, String::new()),
+        Err(e) => e.to_response(),
+    })
+}
+
+// handler to force the stored init_time to a given number
+pub(crate) async fn init_time_force_handler(format: OutputFormat, kv_store: KvStoreDb, bytes: Bytes) -> Result<impl warp::Reply, warp::Rejection> {
+    let init_time = async {
+        let init_time = bytes_to_i64(bytes)?;
+        legacy_int(INIT_TIME_KEY, kv_store.set(LEGACY_NAMESPACE, INIT_TIME_KEY, KvValue::Int(init_time), None).await?)
+    }.await;
+    Ok(match init_time {
+        Ok(init_time) => render_init_time(format, Some(init_time), init_time.to_string()),
+        Err(e) => e.to_response(),
+    })
+}
+
+// handler to peek the init_time without modification
+pub(crate) async fn init_time_peek_handler(format: OutputFormat, kv_store: KvStoreDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let init_time = match kv_store.get(LEGACY_NAMESPACE, INIT_TIME_KEY) {
+        Ok(entry) => legacy_int(INIT_TIME_KEY, entry).map(Some),
+        Err(Error::KeyNotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    };
+    Ok(match init_time {
+        Ok(init_time) => render_init_time(format, init_time, option_to_string(init_time)),
+        Err(e) => e.to_response(),
+    })
+}
+
+// the init_time routes differ only in their text format
+fn render_init_time(format: OutputFormat, init_time: Option<i64>, text: String) -> http::Result<Response<String>> {
+    render(Response::builder().status(StatusCode::OK), format, &InitTime { init_time }, |_| text)
+}
+
+// handler to count up from zero
+pub(crate) async fn counter_handler(format: OutputFormat, kv_store: KvStoreDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let counter = match kv_store.count(LEGACY_NAMESPACE, COUNTER_KEY).await.and_then(|entry| legacy_int(COUNTER_KEY, entry)) {
+        Ok(counter) => Counter { counter: Some(counter) },
+        Err(e) => return Ok(e.to_response()),
+    };
+    Ok(render(Response::builder().status(StatusCode::OK), format, &counter, |counter| option_to_string(counter.counter)))
+}
+
+// the legacy routes only store integers, but their keys can also be written through /kv
+fn legacy_int(key: &str, entry: KvEntry) -> Result<i64, Error> {
+    match entry.value {
+        KvValue::Int(value) => Ok(value),
+        value => Err(Error::WrongType(format!("{}/{} is a {}, not an integer", LEGACY_NAMESPACE, key, value.type_name()))),
+    }
+}
+
+/// What a `/kv` request does to its key. Everything but reads and deletes is a `POST`, to suit Logix.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KvOperation {
+    Get,
+    Set,
+    /// set if absent
+    Init,
+    CompareAndSwap,
+    Increment,
+    Decrement,
+    Delete,
+}
+
+/// the `POST /kv/{namespace}/{key}/{operation}` path segment
+impl FromStr for KvOperation {
+    type Err = ();
+
+    fn from_str(operation: &str) -> Result<Self, Self::Err> {
+        match operation {
+            "set" => Ok(KvOperation::Set),
+            "init" => Ok(KvOperation::Init),
+            "cas" => Ok(KvOperation::CompareAndSwap),
+            "incr" => Ok(KvOperation::Increment),
+            "decr" => Ok(KvOperation::Decrement),
+            "delete" => Ok(KvOperation::Delete),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct KvQuery {
+    /// how to parse the body: auto, int, float or string
+    #[serde(rename = "type")]
+    value_type: Option<String>,
+    /// seconds until the entry expires
+    ttl: Option<String>,
+    /// for compare-and-swap, the value the key must currently hold; absent means the key must be unset
+    expected: Option<String>,
+}
+
+impl KvQuery {
+    fn value_type(&self) -> Result<ValueType, Error> {
+        match self.value_type.as_deref() {
+            Some(name) => ValueType::parse(name).ok_or_else(|| Error::InvalidQuery(format!(
+                "invalid value \"{}\" for type, expected one of auto, int, float, string", name
+            ))),
+            None => Ok(ValueType::Auto),
+        }
+    }
+
+    fn ttl(&self) -> Result<Option<chrono::Duration>, Error> {
+        match parse_param::<u32>("ttl", &self.ttl)? {
+            Some(0) => Err(Error::InvalidQuery("ttl must be at least 1".to_string())),
+            ttl => Ok(ttl.map(|ttl| chrono::Duration::seconds(ttl.into()))),
+        }
+    }
+}
+
+// GET, POST and DELETE /kv/{namespace}/{key}[/{operation}]. The text format is just the resulting value.
+pub(crate) async fn kv_handler(namespace: String, key: String, operation: KvOperation, body: Bytes, format: OutputFormat, query: KvQuery, kv_store: KvStoreDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let entry = match kv_operation(&namespace, &key, operation, &query, &body, &kv_store).await {
+        Ok(entry) => entry,
+        Err(e) => return Ok(e.to_response())
+    };
+    let document = KeyValue {
+        namespace,
+        key,
+        value: entry.value,
+        expires_at: entry.expires_at,
+    };
+    Ok(render(Response::builder().status(StatusCode::OK), format, &document, |document| document.value.to_string()))
+}
+
+async fn kv_operation(namespace: &str, key: &str, operation: KvOperation, query: &KvQuery, body: &Bytes, kv_store: &KvStore) -> Result<KvEntry, Error> {
+    let value_type = query.value_type()?;
+    let ttl = query.ttl()?;
+    let body = std::str::from_utf8(body.borrow()).map_err(|e| Error::InvalidValue(format!("body is not UTF-8: {}", e)))?;
+    match operation {
+        KvOperation::Get => kv_store.get(namespace, key),
+        KvOperation::Set => kv_store.set(namespace, key, KvValue::parse(body, value_type)?, ttl).await,
+        KvOperation::Init => kv_store.set_if_absent(namespace, key, KvValue::parse(body, value_type)?, ttl).await,
+        KvOperation::CompareAndSwap => kv_store.compare_and_swap(namespace, key, query.expected.as_deref(), KvValue::parse(body, value_type)?, ttl).await,
+        KvOperation::Increment | KvOperation::Decrement => {
+            // an empty body steps by one
+            let amount = if body.is_empty() {
+                KvValue::Int(1)
+            } else {
+                KvValue::parse(body, value_type)?
+            };
+            let amount = match (operation, amount) {
+                (KvOperation::Decrement, KvValue::Int(amount)) => KvValue::Int(amount.checked_neg()
+                    .ok_or_else(|| Error::InvalidValue(format!("cannot decrement by {}", amount)))?),
+                (KvOperation::Decrement, KvValue::Float(amount)) => KvValue::Float(-amount),
+                (_, amount) => amount,
+            };
+            kv_store.increment(namespace, key, amount, ttl).await
+        }
+        KvOperation::Delete => kv_store.delete(namespace, key).await,
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TimerQuery {
+    /// count down from this many milliseconds, or `none` to remove the target
+    duration_ms: Option<String>,
+    /// count down to this Unix time in milliseconds
+    end: Option<String>,
+}
+
+impl TimerQuery {
+    fn target(&self) -> Result<Option<TargetChange>, Error> {
+        match (self.duration_ms.as_deref(), self.end.as_deref()) {
+            (Some(_), Some(_)) => Err(Error::InvalidQuery("only one of duration_ms and end may be given".to_string())),
+            (Some("none"), None) => Ok(Some(TargetChange::Clear)),
+            (Some(_), None) => match parse_param::<i64>("duration_ms", &self.duration_ms)? {
+                Some(duration_ms) if !(0..=timers::MAX_TARGET_MS).contains(&duration_ms) => {
+                    Err(Error::InvalidQuery(format!("duration_ms must be between 0 and {}", timers::MAX_TARGET_MS)))
+                }
+                duration_ms => Ok(duration_ms.map(|duration_ms| TargetChange::Duration(chrono::Duration::milliseconds(duration_ms)))),
+            },
+            (None, Some(_)) => match parse_param::<i64>("end", &self.end)? {
+                Some(end) => {
+                    let from_now = end.checked_sub(Utc::now().timestamp_millis());
+                    if !from_now.is_some_and(|from_now| (-timers::MAX_TARGET_MS..=timers::MAX_TARGET_MS).contains(&from_now)) {
+                        return Err(Error::InvalidQuery(format!("end must be within {} milliseconds of now", timers::MAX_TARGET_MS)));
+                    }
+                    match Utc.timestamp_millis_opt(end).single() {
+                        Some(end) => Ok(Some(TargetChange::EndsAt(end))),
+                        None => Err(Error::InvalidQuery(format!("end {} is not a valid time", end))),
+                    }
+                }
+                None => Ok(None),
+            },
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+// GET /timer/{name} and POST /timer/{name}/{operation}
+pub(crate) async fn timer_handler(name: String, operation: TimerOperation, format: OutputFormat, query: TimerQuery, timers: TimersDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let timer = match query.target().and_then(|target| timers.apply(&name, operation, target)) {
+        Ok(timer) => timer,
+        Err(e) => return Ok(e.to_response())
+    };
+    let state = timer_state(name, &timer);
+    Ok(render(Response::builder().status(StatusCode::OK), format, &state, timer_text))
+}
+
+// WEBSOCKET /timerfeed/{name}: the timer's state on connect, then again every time it changes
+pub(crate) async fn timerfeed_handler(name: String, format: OutputFormat, websocket: warp::ws::WebSocket, timers: TimersDb) {
+    let (mut tx, mut rx) = websocket.split();
+    // subscribe first, so a change made while the first state is being sent is not missed
+    let mut changes = timers.subscribe();
+    let mut timer = match timers.get(&name) {
+        Ok(timer) => Some(timer),
+        Err(e) => {
+            let message = match format {
+                OutputFormat::Text => format!("error {}", e),
+                OutputFormat::Json => e.to_json(),
+            };
+            let _ = tx.send(warp::ws::Message::text(message)).await;
+            let _ = tx.close().await;
+            return;
+        }
+    };
+    loop {
+        if let Some(timer) = timer.take() {
+            let state = timer_state(name.clone(), &timer);
+            let message = match format {
+                OutputFormat::Text => timer_text(&state),
+                OutputFormat::Json => match serde_json::to_string(&state) {
+                    Ok(json) => json,
+                    Err(e) => Error::Serialization(e).to_json(),
+                },
+            };
+            if let Err(e) = tx.send(warp::ws::Message::text(message)).await {
+                eprintln!("/timerfeed/{}: {}", name, e);
+                return;
+            }
+        }
+        timer = tokio::select! {
+            message = rx.next() => match message {
+                // clients have nothing to say, but reading is how we notice they have gone
+                Some(Ok(message)) if !message.is_close() => continue,
+                _ => return,
+            },
+            change = changes.recv() => match change {
+                Ok((changed_name, changed_timer)) if changed_name == name => Some(changed_timer),
+                Ok(_) => continue,
+                // we missed some changes, so just send the latest state
+                Err(broadcast::error::RecvError::Lagged(_)) => timers.get(&name).ok(),
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+        };
+    }
+}
+
+fn timer_state(name: String, timer: &Timer) -> TimerState {
+    let now = Utc::now();
+    TimerState {
+        name,
+        status: timer.status_name(),
+        elapsed_ms: timer.elapsed(now).num_milliseconds(),
+        remaining_ms: timer.remaining(now).map(|remaining| remaining.num_milliseconds()),
+        target_ms: timer.target.map(|target| target.num_milliseconds()),
+        ends_at: timer.ends_at(),
+        server_time_ms: now.timestamp_millis(),
+    }
+}
+
+// `running 12345 47655`: status, elapsed and remaining milliseconds, with `-` for no target
+fn timer_text(state: &TimerState) -> String {
+    let remaining = state.remaining_ms.map_or_else(|| "-".to_string(), |remaining| remaining.to_string());
+    format!("{} {} {}", state.status, state.elapsed_ms, remaining)
+}
+
+// GET /time handler
+pub(crate) fn time_handler(format: OutputFormat) -> http::Result<Response<String>> {
+    let unix_ms = Utc::now().timestamp_millis();
+    let time = ServerTime {
+        unix_ms,
+        iso_8601: Utc.timestamp_millis(unix_ms),
+    };
+    render(Response::builder().status(StatusCode::OK), format, &time, |time| {
+        format!("{} {}", time.unix_ms, time.iso_8601.to_rfc3339_opts(SecondsFormat::Millis, true))
+    })
+}
+
+// WEBSOCKET /timesync: reply to every message with it echoed back, plus when we received it and when we replied
+pub(crate) async fn timesync_handler(format: OutputFormat, websocket: warp::ws::WebSocket) {
+    let (mut tx, mut rx) = websocket.split();
+    while let Some(result) = rx.next().await {
+        let server_receive_ms = Utc::now().timestamp_millis();
+        let message = match result {
+            Ok(message) => message,
+            Err(e) => {
+                eprintln!("/timesync: message error: {}", e);
+                break;
+            }
+        };
+        // pings and binary messages are not time requests
+        let client_time = match message.to_str() {
+            Ok(text) => text.trim().to_string(),
+            Err(_) => continue,
+        };
+        let reply = match format {
+            OutputFormat::Text => format!("{} {} {}", client_time, server_receive_ms, Utc::now().timestamp_millis()),
+            OutputFormat::Json => {
+                let reply = TimeSyncReply {
+                    client_time: serde_json::from_str(&client_time).unwrap_or(serde_json::Value::String(client_time)),
+                    server_receive_ms,
+                    server_send_ms: Utc::now().timestamp_millis(),
+                };
+                match serde_json::to_string(&reply) {
+                    Ok(json) => json,
+                    Err(e) => Error::Serialization(e).to_json(),
+                }
+            }
+        };
+        if let Err(e) = tx.send(warp::ws::Message::text(reply)).await {
+            eprintln!("/timesync: error sending reply: {}", e);
+            break;
+        }
+    }
+}
+
+/// scores shown by `?around=` without a `limit`
+const DEFAULT_AROUND_LIMIT: usize = 10;
+
+#[derive(Deserialize)]
+pub(crate) struct LeaderboardQuery {
+    /// center the page on this rank instead of starting at `offset`
+    around: Option<String>,
+}
+
+impl LeaderboardQuery {
+    // the page to show out of `total` scores; a page around a rank near either end of the board is kept full
+    fn page(&self, page: Page, total: usize) -> Result<Page, Error> {
+        match parse_param::<usize>("around", &self.around)? {
+            Some(0) => Err(Error::InvalidQuery("around must be at least 1".to_string())),
+            Some(_) if page.offset != 0 => Err(Error::InvalidQuery("only one of around and offset may be given".to_string())),
+            Some(around) => {
+                let limit = page.limit.unwrap_or(DEFAULT_AROUND_LIMIT);
+                let offset = (around - 1).saturating_sub(limit / 2).min(total.saturating_sub(limit));
+                Ok(Page { offset, limit: Some(limit), ..page })
+            }
+            None => Ok(page),
+        }
+    }
+}
+
+// GET /leaderboard/{board}
+pub(crate) async fn leaderboard_handler(board: String, format: OutputFormat, page: Page, sanitize: SanitizePolicy, query: LeaderboardQuery, leaderboards: LeaderboardsDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let (options, ranking, page) = match leaderboards.ranking(&board)
+        .and_then(|(options, ranking)| Ok((options, query.page(page, ranking.len())?, ranking)))
+    {
+        Ok((options, page, ranking)) => (options, ranking, page),
+        Err(e) => return Ok(e.to_response())
+    };
+    let (scores, page_info) = page.apply(ranking);
+    let leaderboard = Leaderboard {
+        board,
+        order: options.order.name(),
+        keep: options.keep.name(),
+        scores: scores.into_iter().map(leaderboard_entry).collect(),
+        page: page_info,
+    };
+    let response = page_headers(Response::builder().status(StatusCode::OK), &page_info);
+    Ok(render(response, format, &leaderboard, |leaderboard| {
+        // the name goes last, since it may contain spaces
+        let mut lines = leaderboard.scores.iter()
+            .map(|entry| format!("{} {} {}", entry.rank, entry.score, sanitize.sanitize(&entry.player)))
+            .collect::<Vec<String>>();
+        if page.footer {
+            lines.push(leaderboard.page.footer());
+        }
+        lines.join("\n")
+    }))
+}
+
+// DELETE /leaderboard/{board}
+pub(crate) async fn clear_leaderboard_handler(board: String, format: OutputFormat, leaderboards: LeaderboardsDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let removed = match leaderboards.clear(&board).await {
+        Ok(removed) => removed,
+        Err(e) => return Ok(e.to_response())
+    };
+    Ok(render(Response::builder().status(StatusCode::OK), format, &BoardCleared { board, removed }, |cleared| cleared.removed.to_string()))
+}
+
+/// What a request to `/leaderboard/{board}/score` does
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ScoreOperation {
+    Get,
+    Submit,
+    Remove,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ScoreQuery {
+    player: Option<String>,
+    user_id: Option<String>,
+    /// `higher` or `lower`, for a submission that creates the board
+    order: Option<String>,
+    /// `best` or `latest`, for a submission that creates the board
+    keep: Option<String>,
+}
+
+impl ScoreQuery {
+    // boards know players by user ID if they have one, and by name otherwise
+    fn player(&self) -> Result<PlayerKey, Error> {
+        match (&self.user_id, &self.player) {
+            (Some(user_id), _) => Ok(PlayerKey::UserId(user_id.clone())),
+            (None, Some(player)) => Ok(PlayerKey::Name(player.clone())),
+            (None, None) => Err(Error::InvalidQuery("player or user_id is required".to_string())),
+        }
+    }
+}
+
+// GET, POST and DELETE /leaderboard/{board}/score?player={name}
+pub(crate) async fn score_handler(board: String, operation: ScoreOperation, body: Bytes, format: OutputFormat, query: ScoreQuery, leaderboards: LeaderboardsDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let (ranked, updated) = match score_operation(&board, operation, &query, &body, &leaderboards).await {
+        Ok(result) => result,
+        Err(e) => return Ok(e.to_response())
+    };
+    let document = PlayerScore {
+        board,
+        entry: leaderboard_entry(ranked),
+        updated,
+    };
+    Ok(render(Response::builder().status(StatusCode::OK), format, &document, |document| match document.updated {
+        Some(updated) => format!("{} {} {}", document.entry.rank, document.entry.score, updated),
+        None => format!("{} {}", document.entry.rank, document.entry.score),
+    }))
+}
+
+async fn score_operation(board: &str, operation: ScoreOperation, query: &ScoreQuery, body: &Bytes, leaderboards: &Leaderboards) -> Result<(RankedScore, Option<bool>), Error> {
+    let player = query.player()?;
+    match operation {
+        ScoreOperation::Get => Ok((leaderboards.rank(board, &player)?, None)),
+        ScoreOperation::Remove => Ok((leaderboards.remove(board, &player).await?, None)),
+        ScoreOperation::Submit => {
+            let body = std::str::from_utf8(body.borrow()).map_err(|e| Error::InvalidValue(format!("body is not UTF-8: {}", e)))?;
+            let score = body.trim().parse::<f64>()
+                .map_err(|e| Error::InvalidValue(format!("\"{}\" is not a number: {}", body, e)))?;
+            let score = Score {
+                // players submitted with only a user ID are named after it
+                player: query.player.clone().unwrap_or_else(|| player.as_str().to_string()),
+                user_id: query.user_id.clone(),
+                score,
+                submitted_at: Utc::now(),
+            };
+            let order = parse_param::<ScoreOrder>("order", &query.order)?;
+            let keep = parse_param::<KeepPolicy>("keep", &query.keep)?;
+            let (ranked, updated) = leaderboards.submit(board, score, order, keep).await?;
+            Ok((ranked, Some(updated)))
+        }
+    }
+}
+
+fn leaderboard_entry(ranked: RankedScore) -> LeaderboardEntry {
+    LeaderboardEntry {
+        rank: ranked.rank,
+        player: ranked.score.player,
+        user_id: ranked.score.user_id,
+        score: ranked.score.score,
+        submitted_at: ranked.score.submitted_at,
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RoomQuery {
+    /// replay up to this many of the room's recent messages on joining
+    history: Option<String>,
+    /// the member limit, if this member is the first to join
+    max_members: Option<String>,
+}
+
+// GET /room/{name}
+pub(crate) async fn room_status_handler(name: String, format: OutputFormat, rooms: RoomsDb) -> Result<impl warp::Reply, warp::Rejection> {
+    let status = match rooms.status(&name) {
+        Ok(status) => status,
+        Err(e) => return Ok(e.to_response())
+    };
+    let info = RoomInfo {
+        room: name,
+        members: status.members,
+        max_members: status.max_members,
+        retained: status.retained,
+    };
+    Ok(render(Response::builder().status(StatusCode::OK), format, &info, |info| {
+        format!("{}/{}", info.members, info.max_members)
+    }))
+}
+
+// WEBSOCKET /room/{name}: relay text messages between everyone connected to the same room
+pub(crate) fn room_handler(name: String, websocket: warp::ws::Ws, format: OutputFormat, query: RoomQuery, rooms: RoomsDb) -> warp::reply::Response {
+    // join before upgrading, so a full room or bad parameters get an ordinary error response
+    let joined = parse_param::<usize>("history", &query.history)
+        .and_then(|replay| Ok((replay.unwrap_or(0), parse_param::<usize>("max_members", &query.max_members)?)))
+        .and_then(|(replay, max_members)| rooms.join(&name, max_members, replay));
+    let (membership, history) = match joined {
+        Ok(joined) => joined,
+        Err(e) => return e.to_response().into_response()
+    };
+    websocket.on_upgrade(move |websocket| async move {
+        if let Err(e) = room_member(membership, history, format, websocket).await {
+            eprintln!("/room/{}: {}", name, e);
+        }
+    }).into_response()
+}
+
+// the membership is dropped, leaving the room, when the connection ends
+async fn room_member(mut membership: Membership, history: Vec<RoomMessage>, format: OutputFormat, websocket: warp::ws::WebSocket) -> Result<(), warp::Error> {
+    let (mut tx, mut rx) = websocket.split();
+    for message in history {
+        tx.send(warp::ws::Message::text(room_message(format, Ok(room_feed_event(&message, true))))).await?;
+    }
+    loop {
+        let event = tokio::select! {
+            message = rx.next() => match message {
+                Some(Ok(message)) if message.is_close() => return Ok(()),
+                Some(Ok(message)) => match message.to_str() {
+                    Ok(text) => match membership.publish(text) {
+                        Ok(()) => continue,
+                        Err(e) => Err(e),
+                    },
+                    // only text messages are relayed
+                    Err(()) => continue,
+                },
+                Some(Err(e)) => return Err(e),
+                None => return Ok(()),
+            },
+            event = membership.recv() => match event {
+                Ok(event) if !event.is_for(&membership) => continue,
+                Ok(RoomEvent::Message(message)) => Ok(room_feed_event(&message, false)),
+                Ok(RoomEvent::Members(members)) => Ok(RoomFeedEvent::Members { members }),
+                Err(broadcast::error::RecvError::Lagged(count)) => Ok(RoomFeedEvent::Missed { count }),
+                // the room outlives its members
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        };
+        tx.send(warp::ws::Message::text(room_message(format, event))).await?;
+    }
+}
+
+fn room_feed_event(message: &RoomMessage, replayed: bool) -> RoomFeedEvent {
+    RoomFeedEvent::Message {
+        message: message.text.to_string(),
+        sent_at: message.sent_at,
+        replayed,
+    }
+}
+
+// text messages are the event name followed by its data: "message hello", "history hello", "members 3" or "missed 2"
+fn room_message(format: OutputFormat, event: Result<RoomFeedEvent, Error>) -> String {
+    match (format, event) {
+        (OutputFormat::Text, Ok(RoomFeedEvent::Message { message, replayed: false, .. })) => format!("message {}", message),
+        (OutputFormat::Text, Ok(RoomFeedEvent::Message { message, replayed: true, .. })) => format!("history {}", message),
+        (OutputFormat::Text, Ok(RoomFeedEvent::Members { members })) => format!("members {}", members),
+        (OutputFormat::Text, Ok(RoomFeedEvent::Missed { count })) => format!("missed {}", count),
+        (OutputFormat::Text, Err(e)) => format!("error {}", e),
+        (OutputFormat::Json, Ok(event)) => match serde_json::to_string(&event) {
+            Ok(json) => json,
+            Err(e) => Error::Serialization(e).to_json(),
+        },
+        (OutputFormat::Json, Err(e)) => e.to_json(),
+    }
+}
+
+// convert an option to a pretty string
+fn option_to_string<T: fmt::Display>(x: Option<T>) -> String {
+    match x {
+        Some(value) => format!("Some({})", value),
+        None => "None".to_string(),
+    }
+}
+
+// bytes --> utf8 string --> i64
+fn bytes_to_i64(bytes: Bytes) -> Result<i64, Error> {
+    let value = std::str::from_utf8(bytes.borrow()).map_err(|e| Error::InvalidValue(format!("body is not UTF-8: {}", e)))?;
+    value.parse::<i64>().map_err(|e| Error::InvalidValue(format!("\"{}\" is not an integer: {}", value, e)))
+}
+
+// GET /systemstat handler. The text format has its own layout, so it is not rendered from the JSON document.
+pub(crate) fn system_stat_handler(format: OutputFormat) -> http::Result<Response<String>> {
+    let response = Response::builder().status(StatusCode::OK);
+    match format {
+        OutputFormat::Text => response.body(get_system_stat()),
+        OutputFormat::Json => json_body(response, &collect_system_stats()),
+    }
+}
+
+fn get_system_stat() -> String {
+    let sys = systemstat::System::new();
+
+    let mounts = match sys.mounts() {
+        Ok(mounts) => {
+            let mut string = String::from("Mounts:");
+            for mount in mounts.iter() {
+                string.push_str(
+                    format!(
+                        "\n    {} --- {} ---> {} (available {} of {})",
+                        mount.fs_mounted_from, mount.fs_type, mount.fs_mounted_on, mount.avail, mount.total
+                    ).as_str()
+                );
+            }
+            string
+        }
+        Err(x) => format!("Mounts: error: {}", x)
+    };
+
+    let block_device_statistics = match sys.block_device_statistics() {
+        Ok(stats) => {
+            let mut string = String::from("Block devices:");
+            for blkstats in stats.values() {
+                string.push_str(format!("\n    {}: {:?}", blkstats.name, blkstats).as_str());
+            }
+            string
+        }
+        Err(x) => format!("Block devices: error: {}", x)
+    };
+
+    let networks = match sys.networks() {
+        Ok(netifs) => {
+            let mut string = String::from("Networks:");
+            for netif in netifs.values() {
+                string.push_str(format!("\n    {} ({:?})", netif.name, netif.addrs).as_str());
+            }
+            string
+        }
+        Err(x) => format!("Networks: error: {}", x)
+    };
+
+    let interfaces = match sys.networks() {
+        Ok(netifs) => {
+            let mut string = String::from("Interfaces:");
+            for netif in netifs.values() {
+                string.push_str(format!("\n    {} statistics: ({:?})", netif.name, sys.network_stats(&netif.name)).as_str());
+            }
+            string
+        }
+        Err(x) => format!("Interfaces: error: {}", x)
+    };
+
+    let battery = match sys.battery_life() {
+        Ok(battery) => battery_text(battery.remaining_capacity, battery.remaining_time),
+        Err(x) => format!("Battery: error: {}", x)
+    };
+
+    let power = match sys.on_ac_power() {
+        Ok(power) => format!(", AC power: {}", power),
+        Err(x) => format!(", AC power: error: {}", x)
+    };
+
+    let memory = match sys.memory() {
+        Ok(mem) => format!("Memory: {} used / {} ({} bytes) total ({:?})", systemstat::saturating_sub_bytes(mem.total, mem.free), mem.total, mem.total.as_u64(), mem.platform_memory),
+        Err(x) => format!("Memory: error: {}", x)
+    };
+
+    let load = match sys.load_average() {
+        Ok(loadavg) => format!("Load average: {} {} {}", loadavg.one, loadavg.five, loadavg.fifteen),
+        Err(x) => format!("Load average: error: {}", x)
+    };
+
+    let uptime = match sys.uptime() {
+        Ok(uptime) => uptime_text(uptime),
+        Err(x) => format!("Uptime: error: {}", x)
+    };
+
+    let boot_time = match sys.boot_time() {
+        Ok(boot_time) => format!("Boot time: {}", boot_time),
+        Err(x) => format!("Boot time: error: {}", x)
+    };
+
+    // match sys.cpu_load_aggregate() {
+    //     Ok(cpu)=> {
+    //         println!("\nMeasuring CPU load...");
+    //         thread::sleep(Duration::from_secs(1));
+    //         let cpu = cpu.done().unwrap();
+    //         println!("CPU load: {}% user, {}% nice, {}% system, {}% intr, {}% idle ",
+    //                  cpu.user * 100.0, cpu.nice * 100.0, cpu.system * 100.0, cpu.interrupt * 100.0, cpu.idle * 100.0);
+    //     },
+    //     Err(x) => println!("\nCPU load: error: {}", x)
+    // }
+
+    let cpu_temp = match sys.cpu_temp() {
+        Ok(cpu_temp) => format!("CPU temp: {}", cpu_temp),
+        Err(x) => format!("CPU temp: {}", x)
+    };
+
+    let socket_stats = match sys.socket_stats() {
+        Ok(stats) => format!("System socket statistics: {:?}", stats),
+        Err(x) => format!("System socket statistics: error: {}", x)
+    };
+
+    format!(
+        "{}\n{}\n{}\n{}\n{}{}\n{}\n{}\n{}\n{}\n{}\n{}",
+        mounts,
+        block_device_statistics,
+        networks,
+        interfaces,
+        battery,
+        power,
+        memory,
+        load,
+        uptime,
+        boot_time,
+        cpu_temp,
+        socket_stats
+    )
+}
+
+fn battery_text(remaining_capacity: f32, remaining_time: Duration) -> String {
+    format!("Battery: {}%, {} remaining", remaining_capacity * 100.0, DurationFormat::Compact.format(remaining_time.as_secs() as i64))
+}
+
+fn uptime_text(uptime: Duration) -> String {
+    format!("Uptime: {}", DurationFormat::Compact.format(uptime.as_secs() as i64))
+}
+
+fn collect_system_stats() -> SystemStats {
+    let sys = systemstat::System::new();
+
+    let mounts = sys.mounts().map(|mounts| {
+        mounts.iter().map(|mount| response_dto::Mount {
+            mounted_from: mount.fs_mounted_from.clone(),
+            fs_type: mount.fs_type.clone(),
+            mounted_on: mount.fs_mounted_on.clone(),
+            available_bytes: mount.avail.as_u64(),
+            total_bytes: mount.total.as_u64(),
+        }).collect()
+    });
+
+    let block_devices = sys.block_device_statistics().map(|stats| {
+        stats.values().map(|blkstats| format!("{:?}", blkstats)).collect()
+    });
+
+    let networks = sys.networks().map(|netifs| {
+        netifs.values().map(|netif| response_dto::NetworkInterface {
+            name: netif.name.clone(),
+            addresses: netif.addrs.iter().map(|addr| format!("{:?}", addr.addr)).collect(),
+            statistics: sys.network_stats(&netif.name).map(|stats| response_dto::NetworkStatistics {
+                rx_bytes: stats.rx_bytes.as_u64(),
+                tx_bytes: stats.tx_bytes.as_u64(),
+                rx_packets: stats.rx_packets,
+                tx_packets: stats.tx_packets,
+                rx_errors: stats.rx_errors,
+                tx_errors: stats.tx_errors,
+            }).into(),
+        }).collect()
+    });
+
+    let battery = sys.battery_life().map(|battery| response_dto::Battery {
+        remaining_percent: battery.remaining_capacity * 100.0,
+        remaining_secs: battery.remaining_time.as_secs(),
+    });
+
+    let memory = sys.memory().map(|mem| response_dto::Memory {
+        used_bytes: systemstat::saturating_sub_bytes(mem.total, mem.free).as_u64(),
+        total_bytes: mem.total.as_u64(),
+    });
+
+    let load_average = sys.load_average().map(|loadavg| response_dto::LoadAverage {
+        one: loadavg.one,
+        five: loadavg.five,
+        fifteen: loadavg.fifteen,
+    });
+
+    let socket_stats = sys.socket_stats().map(|stats| response_dto::SocketStats {
+        tcp_sockets_in_use: stats.tcp_sockets_in_use,
+        tcp_sockets_orphaned: stats.tcp_sockets_orphaned,
+        udp_sockets_in_use: stats.udp_sockets_in_use,
+        tcp6_sockets_in_use: stats.tcp6_sockets_in_use,
+        udp6_sockets_in_use: stats.udp6_sockets_in_use,
+    });
+
+    SystemStats {
+        mounts: mounts.into(),
+        block_devices: block_devices.into(),
+        networks: networks.into(),
+        battery: battery.into(),
+        ac_power: sys.on_ac_power().into(),
+        memory: memory.into(),
+        load_average: load_average.into(),
+        uptime_secs: sys.uptime().map(|uptime| uptime.as_secs()).into(),
+        boot_time: sys.boot_time().map(|boot_time| boot_time.to_rfc3339_opts(SecondsFormat::Secs, true)).into(),
+        cpu_temp: sys.cpu_temp().into(),
+        socket_stats: socket_stats.into(),
+    }
+}
+
+fn format_registration_date(registration_date: &DateTime<Utc>) -> String {
+    registration_date.date().naive_local().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the ranks on the page shown around a rank
+    fn around(around: usize, limit: Option<usize>, total: usize) -> (usize, usize) {
+        let query = LeaderboardQuery { around: Some(around.to_string()) };
+        let page = query.page(Page { limit, ..Page::default() }, total).unwrap();
+        (page.offset + 1, page.offset + page.limit.unwrap())
+    }
+
+    #[test]
+    fn leaderboard_pages_around_a_rank() {
+        assert_eq!(around(50, None, 100), (45, 54));
+        assert_eq!(around(50, Some(3), 100), (49, 51));
+        // pages near either end are kept full
+        assert_eq!(around(1, None, 100), (1, 10));
+        assert_eq!(around(2, None, 100), (1, 10));
+        assert_eq!(around(100, None, 100), (91, 100));
+        assert_eq!(around(3, None, 4), (1, 10));
+        assert_eq!(around(500, None, 100), (91, 100));
+    }
+
+    #[test]
+    fn leaderboard_around_conflicts() {
+        let query = LeaderboardQuery { around: Some("0".to_string()) };
+        assert!(query.page(Page::default(), 10).is_err());
+        let query = LeaderboardQuery { around: Some("5".to_string()) };
+        assert!(query.page(Page { offset: 3, ..Page::default() }, 10).is_err());
+        assert_eq!(LeaderboardQuery { around: None }.page(Page { offset: 3, ..Page::default() }, 10).unwrap().offset, 3);
+    }
+
+    #[test]
+    fn system_stat_durations() {
+        assert_eq!(uptime_text(Duration::from_secs(18727)), "Uptime: 5h12m");
+        assert_eq!(uptime_text(Duration::from_secs(59)), "Uptime: 59s");
+        assert_eq!(battery_text(0.5, Duration::from_secs(5400)), "Battery: 50%, 1h30m remaining");
+        assert_eq!(battery_text(1.0, Duration::ZERO), "Battery: 100%, 0s remaining");
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::de::DeserializeOwned;
+use tokio::sync::Mutex;
+
+use crate::error::Error;
+
+/// The file some in-memory state is persisted to as JSON, such as the key-value store or the leaderboards.
+///
+/// Owners mark the state dirty after each change and then call [`save`](Self::save), which writes it outside of
+/// whatever lock guards the state.
+pub struct JsonFile {
+    /// what the file holds, for error messages
+    description: &'static str,
+    path: PathBuf,
+    /// set whenever the in-memory state has changes that are not on disk yet
+    dirty: AtomicBool,
+    /// serializes writers of the file
+    save_lock: Mutex<()>,
+}
+
+impl JsonFile {
+    /// Load the state from disk. A missing file starts from the default state, but an unreadable one is an error so
+    /// that stored state is never silently replaced.
+    pub fn load<T: DeserializeOwned + Default>(description: &'static str, path: PathBuf) -> Result<(Self, T), Error> {
+        let state = match fs::read_to_string(path.as_path()) {
+            Ok(string) => serde_json::from_str(&string).map_err(Error::Serialization)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => T::default(),
+            Err(e) => return Err(Error::Io(e)),
+        };
+        Ok((JsonFile::new(description, path), state))
+    }
+
+    /// A file to save state to without loading it first, for state that can be rebuilt if the file is unreadable
+    pub fn new(description: &'static str, path: PathBuf) -> Self {
+        JsonFile {
+            description,
+            path,
+            dirty: AtomicBool::new(false),
+            save_lock: Mutex::new(()),
+        }
+    }
+
+    /// Note that the state has changed and needs saving
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    /// Persist the state if it has unsaved changes. `serialize` is only called if there are some, and should lock the
+    /// state just long enough to serialize it. Failures are logged, since the state is still good in memory.
+    pub async fn save<F>(&self, serialize: F)
+        where F: FnOnce() -> serde_json::Result<String>
+    {
+        let _save_guard = self.save_lock.lock().await;
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        let result = match serialize() {
+            Ok(serialized) => {
+                let path = self.path.clone();
+                tokio::task::spawn_blocking(move || write_atomically(&path, serialized))
+                    .await
+                    .expect("JSON file writer panicked")
+            }
+            Err(e) => Err(Error::Serialization(e)),
+        };
+        if let Err(e) = result {
+            eprintln!("Error saving {} to {}: {}", self.description, self.path.display(), e);
+        }
+    }
+}
+
+// Write to a temporary file next to the real one and rename it into place, so that a crash or a full disk part way
+// through leaves the previous file intact rather than one the server refuses to start with
+fn write_atomically(path: &Path, contents: String) -> Result<(), Error> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// A store file in the temporary directory for a test, named after the test and removed when dropped
+#[cfg(test)]
+pub(crate) struct TempFile(PathBuf);
+
+#[cfg(test)]
+impl TempFile {
+    pub fn new(test_name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("neos-api-test-{}-{}.json", std::process::id(), test_name));
+        let _ = fs::remove_file(&path);
+        TempFile(path)
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.0.clone()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn saves_and_loads() {
+        let temp_file = TempFile::new("json-file-saves-and-loads");
+        let path = temp_file.path();
+        let (file, mut state) = JsonFile::load::<BTreeMap<String, i64>>("test state", path.clone()).unwrap();
+        assert!(state.is_empty());
+
+        // nothing is written until the state is marked dirty
+        file.save(|| panic!("clean state should not be serialized")).await;
+        assert!(!path.exists());
+
+        state.insert("answer".to_string(), 42);
+        file.mark_dirty();
+        file.save(|| serde_json::to_string(&state)).await;
+        let (_, loaded) = JsonFile::load::<BTreeMap<String, i64>>("test state", path.clone()).unwrap();
+        assert_eq!(loaded, state);
+
+        fs::write(&path, "{\"answer\":").unwrap();
+        assert!(JsonFile::load::<BTreeMap<String, i64>>("test state", path).is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::json_file::JsonFile;
+use crate::names::check_name;
+
+/// namespace the `/initTime*` and `/counter` routes keep their values in, under [`INIT_TIME_KEY`] and [`COUNTER_KEY`]
+pub const LEGACY_NAMESPACE: &str = "legacy";
+pub const INIT_TIME_KEY: &str = "initTime";
+pub const COUNTER_KEY: &str = "counter";
+
+/// A stored value. Integers and floats can be incremented, strings cannot.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum KvValue {
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+/// The type a request body is parsed as. [`ValueType::Auto`] picks the narrowest type the text fits.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Auto,
+    Int,
+    Float,
+    String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KvEntry {
+    pub value: KvValue,
+    /// the entry is treated as absent from this time on
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "crate::dto::custom_serializer::optional_iso_8601")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// what an operation does to the entry it was given
+enum Outcome {
+    Keep(KvEntry),
+    Store(KvEntry),
+    Remove(KvEntry),
+}
+
+/// Every namespace, by name, and its keys
+type Namespaces = BTreeMap<String, BTreeMap<String, KvEntry>>;
+
+/// Disk-backed store of small values, grouped into namespaces so that several worlds can keep their own state.
+///
+/// Every operation is atomic. The file is written after each change, outside of the map lock.
+pub struct KvStore {
+    store_file: JsonFile,
+    namespaces: Mutex<Namespaces>,
+}
+
+impl KvValue {
+    /// Parse a request body
+    pub fn parse(text: &str, value_type: ValueType) -> Result<Self, Error> {
+        match value_type {
+            ValueType::Auto => Ok(text.parse::<i64>().map(KvValue::Int)
+                .or_else(|_| parse_float(text).map(KvValue::Float))
+                .unwrap_or_else(|_| KvValue::String(text.to_string()))),
+            ValueType::Int => text.parse::<i64>().map(KvValue::Int)
+                .map_err(|e| Error::InvalidValue(format!("\"{}\" is not an integer: {}", text, e))),
+            ValueType::Float => parse_float(text).map(KvValue::Float),
+            ValueType::String => Ok(KvValue::String(text.to_string())),
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            KvValue::Int(_) => "int",
+            KvValue::Float(_) => "float",
+            KvValue::String(_) => "string",
+        }
+    }
+}
+
+fn parse_float(text: &str) -> Result<f64, Error> {
+    match text.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        Ok(_) => Err(Error::InvalidValue(format!("\"{}\" is not a finite number", text))),
+        Err(e) => Err(Error::InvalidValue(format!("\"{}\" is not a number: {}", text, e))),
+    }
+}
+
+/// the text form, which parses back to the same type: floats always have a decimal point or exponent
+impl fmt::Display for KvValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvValue::Int(value) => write!(f, "{}", value),
+            KvValue::Float(value) => write!(f, "{:?}", value),
+            KvValue::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl ValueType {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "auto" => Some(ValueType::Auto),
+            "int" => Some(ValueType::Int),
+            "float" => Some(ValueType::Float),
+            "string" => Some(ValueType::String),
+            _ => None,
+        }
+    }
+}
+
+impl KvEntry {
+    fn new(value: KvValue, ttl: Option<Duration>, now: DateTime<Utc>) -> Self {
+        KvEntry {
+            value,
+            expires_at: ttl.map(|ttl| now + ttl),
+        }
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+impl KvStore {
+    /// Load the store from disk. A missing file starts an empty store, but an unreadable one is an error so that
+    /// stored state is never silently replaced.
+    pub fn load(store_file: PathBuf) -> Result<Self, Error> {
+        let (store_file, namespaces) = JsonFile::load("key-value store", store_file)?;
+        Ok(KvStore {
+            store_file,
+            namespaces: Mutex::new(namespaces),
+        })
+    }
+
+    pub fn get(&self, namespace: &str, key: &str) -> Result<KvEntry, Error> {
+        self.apply(namespace, key, |entry, _| match entry {
+            Some(entry) => Ok(Outcome::Keep(entry.clone())),
+            None => Err(key_not_found(namespace, key)),
+        })
+    }
+
+    /// Store a value, replacing any current one
+    pub async fn set(&self, namespace: &str, key: &str, value: KvValue, ttl: Option<Duration>) -> Result<KvEntry, Error> {
+        self.apply_and_save(namespace, key, |_, now| Ok(Outcome::Store(KvEntry::new(value, ttl, now)))).await
+    }
+
+    /// Store a value only if the key is absent. Either way, the entry that ends up stored is returned.
+    pub async fn set_if_absent(&self, namespace: &str, key: &str, value: KvValue, ttl: Option<Duration>) -> Result<KvEntry, Error> {
+        self.apply_and_save(namespace, key, |entry, now| match entry {
+            Some(entry) => Ok(Outcome::Keep(entry.clone())),
+            None => Ok(Outcome::Store(KvEntry::new(value, ttl, now))),
+        }).await
+    }
+
+    /// Store a value only if the current one, in its text form, is `expected`, or if the key is absent when `expected`
+    /// is `None`
+    pub async fn compare_and_swap(&self, namespace: &str, key: &str, expected: Option<&str>, value: KvValue, ttl: Option<Duration>) -> Result<KvEntry, Error> {
+        self.apply_and_save(namespace, key, |entry, now| {
+            let current = entry.map(|entry| entry.value.to_string());
+            if current.as_deref() == expected {
+                Ok(Outcome::Store(KvEntry::new(value, ttl, now)))
+            } else {
+                Err(Error::CompareFailed(match current {
+                    Some(current) => format!("{}/{} is \"{}\"", namespace, key, current),
+                    None => format!("{}/{} is not set", namespace, key),
+                }))
+            }
+        }).await
+    }
+
+    /// Add to a number, starting from zero if the key is absent. The current expiry is kept unless `ttl` is given.
+    /// Adding a float to an integer stores a float.
+    pub async fn increment(&self, namespace: &str, key: &str, amount: KvValue, ttl: Option<Duration>) -> Result<KvEntry, Error> {
+        self.apply_and_save(namespace, key, |entry, now| incremented(namespace, key, entry, amount, ttl, now)).await
+    }
+
+    /// Store zero if the key is absent and add one otherwise, so that the first caller gets 0, like `/counter`
+    pub async fn count(&self, namespace: &str, key: &str) -> Result<KvEntry, Error> {
+        self.apply_and_save(namespace, key, |entry, now| match entry {
+            Some(_) => incremented(namespace, key, entry, KvValue::Int(1), None, now),
+            None => Ok(Outcome::Store(KvEntry::new(KvValue::Int(0), None, now))),
+        }).await
+    }
+
+    /// Remove a key, returning what it held
+    pub async fn delete(&self, namespace: &str, key: &str) -> Result<KvEntry, Error> {
+        self.apply_and_save(namespace, key, |entry, _| match entry {
+            Some(entry) => Ok(Outcome::Remove(entry.clone())),
+            None => Err(key_not_found(namespace, key)),
+        }).await
+    }
+
+    async fn apply_and_save<F>(&self, namespace: &str, key: &str, operation: F) -> Result<KvEntry, Error>
+        where F: FnOnce(Option<&KvEntry>, DateTime<Utc>) -> Result<Outcome, Error>
+    {
+        let entry = self.apply(namespace, key, operation)?;
+        self.save().await;
+        Ok(entry)
+    }
+
+    // Run an operation against the live entry, treating an expired one as absent, and return the entry it produced.
+    // The lock is held throughout, so operations on the same key are never interleaved.
+    fn apply<F>(&self, namespace: &str, key: &str, operation: F) -> Result<KvEntry, Error>
+        where F: FnOnce(Option<&KvEntry>, DateTime<Utc>) -> Result<Outcome, Error>
+    {
+        check_name("namespace", namespace)?;
+        check_name("key", key)?;
+        let now = Utc::now();
+        let mut namespaces = self.namespaces.lock().expect("kv store poisoned");
+        let entry = namespaces.get(namespace)
+            .and_then(|keys| keys.get(key))
+            .filter(|entry| !entry.is_expired(now));
+        match operation(entry, now)? {
+            Outcome::Keep(entry) => Ok(entry),
+            Outcome::Store(entry) => {
+                namespaces.entry(namespace.to_string()).or_default().insert(key.to_string(), entry.clone());
+                self.store_file.mark_dirty();
+                Ok(entry)
+            }
+            Outcome::Remove(entry) => {
+                if let Some(keys) = namespaces.get_mut(namespace) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        namespaces.remove(namespace);
+                    }
+                }
+                self.store_file.mark_dirty();
+                Ok(entry)
+            }
+        }
+    }
+
+    /// Persist the store if it has unsaved changes, dropping expired entries first
+    async fn save(&self) {
+        self.store_file.save(|| {
+            let now = Utc::now();
+            let mut namespaces = self.namespaces.lock().expect("kv store poisoned");
+            for keys in namespaces.values_mut() {
+                keys.retain(|_, entry| !entry.is_expired(now));
+            }
+            namespaces.retain(|_, keys| !keys.is_empty());
+            serde_json::to_string(&*namespaces)
+        }).await
+    }
+}
+
+// add to an entry, starting from zero if it is absent, keeping its expiry unless `ttl` is given
+fn incremented(namespace: &str, key: &str, entry: Option<&KvEntry>, amount: KvValue, ttl: Option<Duration>, now: DateTime<Utc>) -> Result<Outcome, Error> {
+    let current = entry.map_or(KvValue::Int(0), |entry| entry.value.clone());
+    let value = match (current, amount) {
+        (KvValue::Int(current), KvValue::Int(amount)) => KvValue::Int(current.checked_add(amount)
+            .ok_or_else(|| Error::InvalidValue(format!("{}/{} would overflow", namespace, key)))?),
+        (KvValue::Int(current), KvValue::Float(amount)) => KvValue::Float(current as f64 + amount),
+        (KvValue::Float(current), KvValue::Int(amount)) => KvValue::Float(current + amount as f64),
+        (KvValue::Float(current), KvValue::Float(amount)) => KvValue::Float(current + amount),
+        (KvValue::String(_), _) => return Err(Error::WrongType(format!("{}/{} is a string and cannot be incremented", namespace, key))),
+        (_, KvValue::String(amount)) => return Err(Error::InvalidValue(format!("\"{}\" is not a number", amount))),
+    };
+    // an infinite float would be stored as null, which the store file cannot be loaded with
+    if let KvValue::Float(value) = value {
+        if !value.is_finite() {
+            return Err(Error::InvalidValue(format!("{}/{} would overflow", namespace, key)));
+        }
+    }
+    let expires_at = match ttl {
+        Some(ttl) => Some(now + ttl),
+        None => entry.and_then(|entry| entry.expires_at),
+    };
+    Ok(Outcome::Store(KvEntry { value, expires_at }))
+}
+
+fn key_not_found(namespace: &str, key: &str) -> Error {
+    Error::KeyNotFound(format!("{}/{}", namespace, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_file::TempFile;
+
+    async fn increment(store: &KvStore, amount: KvValue) -> Result<KvValue, Error> {
+        store.increment("world", "counter", amount, None).await.map(|entry| entry.value)
+    }
+
+    #[tokio::test]
+    async fn increment_starts_from_zero() {
+        let temp_file = TempFile::new("kv-increment-starts-from-zero");
+        let store = KvStore::load(temp_file.path()).unwrap();
+        assert_eq!(increment(&store, KvValue::Int(5)).await.unwrap(), KvValue::Int(5));
+        assert_eq!(increment(&store, KvValue::Int(-7)).await.unwrap(), KvValue::Int(-2));
+        assert_eq!(store.get("world", "counter").unwrap().value, KvValue::Int(-2));
+    }
+
+    #[tokio::test]
+    async fn counting_starts_at_zero() {
+        let temp_file = TempFile::new("kv-counting-starts-at-zero");
+        let store = KvStore::load(temp_file.path()).unwrap();
+        assert_eq!(store.count("world", "visits").await.unwrap().value, KvValue::Int(0));
+        assert_eq!(store.count("world", "visits").await.unwrap().value, KvValue::Int(1));
+        store.set("world", "visits", KvValue::String("many".to_string()), None).await.unwrap();
+        assert!(matches!(store.count("world", "visits").await, Err(Error::WrongType(_))));
+    }
+
+    #[tokio::test]
+    async fn increment_promotes_to_float() {
+        let temp_file = TempFile::new("kv-increment-promotes-to-float");
+        let store = KvStore::load(temp_file.path()).unwrap();
+        store.set("world", "counter", KvValue::Int(2), None).await.unwrap();
+        assert_eq!(increment(&store, KvValue::Float(0.5)).await.unwrap(), KvValue::Float(2.5));
+        // once a float, always a float
+        assert_eq!(increment(&store, KvValue::Int(1)).await.unwrap(), KvValue::Float(3.5));
+    }
+
+    #[tokio::test]
+    async fn increment_overflow_keeps_the_value() {
+        let temp_file = TempFile::new("kv-increment-overflow");
+        let store = KvStore::load(temp_file.path()).unwrap();
+        store.set("world", "counter", KvValue::Int(i64::MAX), None).await.unwrap();
+        assert!(matches!(increment(&store, KvValue::Int(1)).await, Err(Error::InvalidValue(_))));
+        assert_eq!(store.get("world", "counter").unwrap().value, KvValue::Int(i64::MAX));
+
+        store.set("world", "counter", KvValue::Float(f64::MAX), None).await.unwrap();
+        assert!(matches!(increment(&store, KvValue::Float(f64::MAX)).await, Err(Error::InvalidValue(_))));
+        assert_eq!(store.get("world", "counter").unwrap().value, KvValue::Float(f64::MAX));
+    }
+
+    #[tokio::test]
+    async fn increment_rejects_strings() {
+        let temp_file = TempFile::new("kv-increment-rejects-strings");
+        let store = KvStore::load(temp_file.path()).unwrap();
+        store.set("world", "counter", KvValue::String("three".to_string()), None).await.unwrap();
+        assert!(matches!(increment(&store, KvValue::Int(1)).await, Err(Error::WrongType(_))));
+
+        store.set("world", "counter", KvValue::Int(3), None).await.unwrap();
+        assert!(matches!(increment(&store, KvValue::String("1".to_string())).await, Err(Error::InvalidValue(_))));
+        assert_eq!(store.get("world", "counter").unwrap().value, KvValue::Int(3));
+    }
+
+    #[tokio::test]
+    async fn increment_keeps_the_expiry_unless_given_one() {
+        let temp_file = TempFile::new("kv-increment-expiry");
+        let store = KvStore::load(temp_file.path()).unwrap();
+        let set = store.set("world", "counter", KvValue::Int(1), Some(Duration::hours(1))).await.unwrap();
+        let kept = store.increment("world", "counter", KvValue::Int(1), None).await.unwrap();
+        assert_eq!(kept.expires_at, set.expires_at);
+        let replaced = store.increment("world", "counter", KvValue::Int(1), Some(Duration::hours(2))).await.unwrap();
+        assert!(replaced.expires_at > set.expires_at);
+
+        // an expired counter starts again from zero
+        store.set("world", "counter", KvValue::Int(10), Some(Duration::zero())).await.unwrap();
+        assert_eq!(increment(&store, KvValue::Int(1)).await.unwrap(), KvValue::Int(1));
+    }
+
+    #[tokio::test]
+    async fn changes_survive_a_reload() {
+        let temp_file = TempFile::new("kv-changes-survive-a-reload");
+        let store = KvStore::load(temp_file.path()).unwrap();
+        increment(&store, KvValue::Int(41)).await.unwrap();
+        increment(&store, KvValue::Int(1)).await.unwrap();
+        let reloaded = KvStore::load(temp_file.path()).unwrap();
+        assert_eq!(reloaded.get("world", "counter").unwrap().value, KvValue::Int(42));
+    }
+}
@@ -0,0 +1,461 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::Error;
+use crate::json_file::JsonFile;
+use crate::names::check_name;
+
+/// Which scores rank first
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoreOrder {
+    /// points, kills and the like
+    Higher,
+    /// times, strokes and the like
+    Lower,
+}
+
+/// What happens when a player who is already on a board submits another score
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum KeepPolicy {
+    /// keep whichever score ranks better
+    Best,
+    /// always keep the new score
+    Latest,
+}
+
+/// A player's score on a board
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Score {
+    pub player: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    pub score: f64,
+    #[serde(with = "crate::dto::custom_serializer::iso_8601")]
+    pub submitted_at: DateTime<Utc>,
+}
+
+/// How a board knows a player. User IDs and names are kept apart, so a player named after someone else's user ID
+/// cannot replace, read or remove that user's score.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum PlayerKey {
+    UserId(String),
+    /// for players submitted without a user ID
+    Name(String),
+}
+
+/// A board's ranking rules, which are fixed when its first score is submitted
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct BoardOptions {
+    pub order: ScoreOrder,
+    pub keep: KeepPolicy,
+}
+
+/// A score and where it ranks, starting from 1. Tied scores are ranked by who submitted theirs first.
+pub struct RankedScore {
+    pub rank: usize,
+    pub score: Score,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Board {
+    #[serde(flatten)]
+    options: BoardOptions,
+    /// stored as a list, since each score carries its own key
+    #[serde(serialize_with = "serialize_scores", deserialize_with = "deserialize_scores")]
+    scores: BTreeMap<PlayerKey, Score>,
+}
+
+/// Disk-backed high score tables, by name.
+///
+/// Boards are created by their first score and forgotten once their last score is removed. The file is written after
+/// each change, outside of the board lock.
+pub struct Leaderboards {
+    leaderboard_file: JsonFile,
+    boards: Mutex<BTreeMap<String, Board>>,
+}
+
+impl PlayerKey {
+    /// the key a score is kept under: its user ID if it has one, otherwise its player name
+    pub fn of(score: &Score) -> Self {
+        match &score.user_id {
+            Some(user_id) => PlayerKey::UserId(user_id.clone()),
+            None => PlayerKey::Name(score.player.clone()),
+        }
+    }
+
+    /// the user ID or name on its own
+    pub fn as_str(&self) -> &str {
+        match self {
+            PlayerKey::UserId(user_id) => user_id,
+            PlayerKey::Name(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for PlayerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerKey::UserId(user_id) => write!(f, "user {}", user_id),
+            PlayerKey::Name(name) => write!(f, "player {}", name),
+        }
+    }
+}
+
+impl ScoreOrder {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScoreOrder::Higher => "higher",
+            ScoreOrder::Lower => "lower",
+        }
+    }
+
+    // better scores sort first, then earlier submissions
+    fn compare(&self, a: &Score, b: &Score) -> Ordering {
+        let by_score = match self {
+            ScoreOrder::Higher => b.score.total_cmp(&a.score),
+            ScoreOrder::Lower => a.score.total_cmp(&b.score),
+        };
+        by_score.then(a.submitted_at.cmp(&b.submitted_at))
+    }
+}
+
+impl FromStr for ScoreOrder {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "higher" => Ok(ScoreOrder::Higher),
+            "lower" => Ok(ScoreOrder::Lower),
+            _ => Err("expected \"higher\" or \"lower\"".to_string()),
+        }
+    }
+}
+
+impl KeepPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeepPolicy::Best => "best",
+            KeepPolicy::Latest => "latest",
+        }
+    }
+}
+
+impl FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "best" => Ok(KeepPolicy::Best),
+            "latest" => Ok(KeepPolicy::Latest),
+            _ => Err("expected \"best\" or \"latest\"".to_string()),
+        }
+    }
+}
+
+/// higher scores are better and each player's best score is kept
+impl Default for BoardOptions {
+    fn default() -> Self {
+        BoardOptions {
+            order: ScoreOrder::Higher,
+            keep: KeepPolicy::Best,
+        }
+    }
+}
+
+impl Board {
+    fn ranked(&self) -> Vec<(&PlayerKey, &Score)> {
+        let mut scores = self.scores.iter().collect::<Vec<(&PlayerKey, &Score)>>();
+        scores.sort_by(|(_, a), (_, b)| self.options.order.compare(a, b));
+        scores
+    }
+
+    fn rank_of(&self, player: &PlayerKey) -> Option<RankedScore> {
+        self.ranked().into_iter()
+            .position(|(key, _)| key == player)
+            .map(|index| RankedScore {
+                rank: index + 1,
+                score: self.scores[player].clone(),
+            })
+    }
+}
+
+impl Leaderboards {
+    /// Load the boards from disk. A missing file starts with no boards, but an unreadable one is an error so that
+    /// scores are never silently replaced.
+    pub fn load(leaderboard_file: PathBuf) -> Result<Self, Error> {
+        let (leaderboard_file, boards) = JsonFile::load("leaderboards", leaderboard_file)?;
+        Ok(Leaderboards {
+            leaderboard_file,
+            boards: Mutex::new(boards),
+        })
+    }
+
+    /// Every score on a board, best first, along with the board's options. A board with no scores is empty rather
+    /// than an error.
+    pub fn ranking(&self, board: &str) -> Result<(BoardOptions, Vec<RankedScore>), Error> {
+        check_name("board name", board)?;
+        let boards = self.boards.lock().expect("leaderboards poisoned");
+        Ok(match boards.get(board) {
+            Some(board) => {
+                let ranking = board.ranked().into_iter()
+                    .enumerate()
+                    .map(|(index, (_, score))| RankedScore {
+                        rank: index + 1,
+                        score: score.clone(),
+                    })
+                    .collect();
+                (board.options, ranking)
+            }
+            None => (BoardOptions::default(), Vec::new()),
+        })
+    }
+
+    /// A player's score and rank
+    pub fn rank(&self, board: &str, player: &PlayerKey) -> Result<RankedScore, Error> {
+        check_name("board name", board)?;
+        let boards = self.boards.lock().expect("leaderboards poisoned");
+        boards.get(board)
+            .and_then(|entry| entry.rank_of(player))
+            .ok_or_else(|| score_not_found(board, player))
+    }
+
+    /// Submit a score, creating the board if needed, and return the player's score and rank afterwards along with
+    /// whether the new score was kept.
+    ///
+    /// `order` and `keep` are used when creating the board; for an existing board, any that are given must match it.
+    pub async fn submit(&self, board: &str, score: Score, order: Option<ScoreOrder>, keep: Option<KeepPolicy>) -> Result<(RankedScore, bool), Error> {
+        check_name("board name", board)?;
+        check_name("player name", &score.player)?;
+        if let Some(user_id) = &score.user_id {
+            check_name("user ID", user_id)?;
+        }
+        if !score.score.is_finite() {
+            return Err(Error::InvalidValue(format!("{} is not a finite number", score.score)));
+        }
+        let result = {
+            let mut boards = self.boards.lock().expect("leaderboards poisoned");
+            let entry = boards.entry(board.to_string()).or_insert_with(|| Board {
+                options: BoardOptions {
+                    order: order.unwrap_or(ScoreOrder::Higher),
+                    keep: keep.unwrap_or(KeepPolicy::Best),
+                },
+                scores: BTreeMap::new(),
+            });
+            let options = entry.options;
+            if order.is_some_and(|order| order != options.order) || keep.is_some_and(|keep| keep != options.keep) {
+                return Err(Error::BoardMismatch(format!("board {} ranks {} scores first and keeps the {} score", board, options.order.name(), options.keep.name())));
+            }
+            let player = PlayerKey::of(&score);
+            let updated = match entry.scores.get(&player) {
+                Some(current) => options.keep == KeepPolicy::Latest || options.order.compare(&score, current) == Ordering::Less,
+                None => true,
+            };
+            if updated {
+                entry.scores.insert(player.clone(), score);
+                self.leaderboard_file.mark_dirty();
+            }
+            (entry.rank_of(&player).expect("a submitted score should be on its board"), updated)
+        };
+        self.save().await;
+        Ok(result)
+    }
+
+    /// Remove a player's score, returning what it was
+    pub async fn remove(&self, board: &str, player: &PlayerKey) -> Result<RankedScore, Error> {
+        check_name("board name", board)?;
+        let removed = {
+            let mut boards = self.boards.lock().expect("leaderboards poisoned");
+            let entry = boards.get_mut(board).ok_or_else(|| score_not_found(board, player))?;
+            let removed = entry.rank_of(player).ok_or_else(|| score_not_found(board, player))?;
+            entry.scores.remove(player);
+            if entry.scores.is_empty() {
+                boards.remove(board);
+            }
+            self.leaderboard_file.mark_dirty();
+            removed
+        };
+        self.save().await;
+        Ok(removed)
+    }
+
+    /// Remove a whole board, returning how many scores it had
+    pub async fn clear(&self, board: &str) -> Result<usize, Error> {
+        check_name("board name", board)?;
+        let removed = {
+            let mut boards = self.boards.lock().expect("leaderboards poisoned");
+            match boards.remove(board) {
+                Some(entry) => {
+                    self.leaderboard_file.mark_dirty();
+                    entry.scores.len()
+                }
+                None => 0,
+            }
+        };
+        self.save().await;
+        Ok(removed)
+    }
+
+    /// Persist the boards if they have unsaved changes
+    async fn save(&self) {
+        self.leaderboard_file.save(|| {
+            let boards = self.boards.lock().expect("leaderboards poisoned");
+            serde_json::to_string(&*boards)
+        }).await
+    }
+}
+
+fn score_not_found(board: &str, player: &PlayerKey) -> Error {
+    Error::ScoreNotFound(format!("{} on board {}", player, board))
+}
+
+fn serialize_scores<S: Serializer>(scores: &BTreeMap<PlayerKey, Score>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(scores.values())
+}
+
+fn deserialize_scores<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<PlayerKey, Score>, D::Error> {
+    let scores = Vec::<Score>::deserialize(deserializer)?;
+    Ok(scores.into_iter().map(|score| (PlayerKey::of(&score), score)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::json_file::TempFile;
+
+    // scores are submitted a second apart, in the order given
+    fn score(player: &str, score: f64, second: i64) -> Score {
+        Score {
+            player: player.to_string(),
+            user_id: None,
+            score,
+            submitted_at: Utc.ymd(2022, 1, 1).and_hms(0, 0, 0) + Duration::seconds(second),
+        }
+    }
+
+    fn name(player: &str) -> PlayerKey {
+        PlayerKey::Name(player.to_string())
+    }
+
+    fn players(leaderboards: &Leaderboards, board: &str) -> Vec<(usize, String, f64)> {
+        leaderboards.ranking(board).unwrap().1.into_iter()
+            .map(|ranked| (ranked.rank, ranked.score.player, ranked.score.score))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn higher_scores_rank_first_by_default() {
+        let temp_file = TempFile::new("leaderboard-higher");
+        let leaderboards = Leaderboards::load(temp_file.path()).unwrap();
+        leaderboards.submit("arena", score("a", 10.0, 0), None, None).await.unwrap();
+        leaderboards.submit("arena", score("b", 30.0, 1), None, None).await.unwrap();
+        let (ranked, kept) = leaderboards.submit("arena", score("c", 10.0, 2), None, None).await.unwrap();
+        assert!(kept);
+        // ties go to whoever got there first
+        assert_eq!(ranked.rank, 3);
+        assert_eq!(players(&leaderboards, "arena"), vec![(1, "b".to_string(), 30.0), (2, "a".to_string(), 10.0), (3, "c".to_string(), 10.0)]);
+        assert_eq!(leaderboards.rank("arena", &name("a")).unwrap().rank, 2);
+    }
+
+    #[tokio::test]
+    async fn lower_scores_rank_first_when_asked() {
+        let temp_file = TempFile::new("leaderboard-lower");
+        let leaderboards = Leaderboards::load(temp_file.path()).unwrap();
+        leaderboards.submit("speedrun", score("a", 61.5, 0), Some(ScoreOrder::Lower), None).await.unwrap();
+        leaderboards.submit("speedrun", score("b", 59.0, 1), None, None).await.unwrap();
+        assert_eq!(players(&leaderboards, "speedrun")[0].1, "b");
+        let (options, _) = leaderboards.ranking("speedrun").unwrap();
+        assert!(options.order == ScoreOrder::Lower);
+
+        // the board's options are fixed by its first score
+        let mismatch = leaderboards.submit("speedrun", score("c", 1.0, 2), Some(ScoreOrder::Higher), None).await;
+        assert!(matches!(mismatch, Err(Error::BoardMismatch(_))));
+        assert!(leaderboards.submit("speedrun", score("c", 1.0, 2), Some(ScoreOrder::Lower), Some(KeepPolicy::Best)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn keep_policies() {
+        let temp_file = TempFile::new("leaderboard-keep");
+        let leaderboards = Leaderboards::load(temp_file.path()).unwrap();
+        leaderboards.submit("best", score("a", 20.0, 0), None, Some(KeepPolicy::Best)).await.unwrap();
+        let (ranked, kept) = leaderboards.submit("best", score("a", 5.0, 1), None, None).await.unwrap();
+        assert!(!kept);
+        assert_eq!(ranked.score.score, 20.0);
+        // an equal score is not better, so the earlier one stays
+        let (ranked, kept) = leaderboards.submit("best", score("a", 20.0, 2), None, None).await.unwrap();
+        assert!(!kept);
+        assert_eq!(ranked.score.submitted_at, score("a", 20.0, 0).submitted_at);
+
+        leaderboards.submit("latest", score("a", 20.0, 0), None, Some(KeepPolicy::Latest)).await.unwrap();
+        let (ranked, kept) = leaderboards.submit("latest", score("a", 5.0, 1), None, None).await.unwrap();
+        assert!(kept);
+        assert_eq!(ranked.score.score, 5.0);
+    }
+
+    #[tokio::test]
+    async fn players_with_user_ids() {
+        let temp_file = TempFile::new("leaderboard-user-ids");
+        let leaderboards = Leaderboards::load(temp_file.path()).unwrap();
+        let renamed = |name: &str, points: f64, second: i64| Score { user_id: Some("U-zkxs".to_string()), ..score(name, points, second) };
+        leaderboards.submit("arena", renamed("zkxs", 10.0, 0), None, None).await.unwrap();
+        leaderboards.submit("arena", renamed("zkxs (new name)", 15.0, 1), None, None).await.unwrap();
+        assert_eq!(players(&leaderboards, "arena"), vec![(1, "zkxs (new name)".to_string(), 15.0)]);
+        assert!(leaderboards.rank("arena", &PlayerKey::UserId("U-zkxs".to_string())).is_ok());
+        assert!(matches!(leaderboards.rank("arena", &name("zkxs")), Err(Error::ScoreNotFound(_))));
+
+        let long_id = Score { user_id: Some("U".repeat(1000)), ..score("a", 1.0, 2) };
+        assert!(matches!(leaderboards.submit("arena", long_id, None, None).await, Err(Error::InvalidQuery(_))));
+        assert!(matches!(leaderboards.submit("arena", score("a", f64::NAN, 3), None, None).await, Err(Error::InvalidValue(_))));
+        assert_eq!(players(&leaderboards, "arena").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn names_cannot_take_over_user_ids() {
+        let temp_file = TempFile::new("leaderboard-impostors");
+        let leaderboards = Leaderboards::load(temp_file.path()).unwrap();
+        let user_id = PlayerKey::UserId("U-zkxs".to_string());
+        leaderboards.submit("arena", Score { user_id: Some("U-zkxs".to_string()), ..score("zkxs", 10.0, 0) }, None, None).await.unwrap();
+        let (ranked, kept) = leaderboards.submit("arena", score("U-zkxs", 99.0, 1), None, None).await.unwrap();
+        assert!(kept);
+        assert_eq!(ranked.score.user_id, None);
+
+        // the impostor gets an entry of their own and the real user's score is untouched
+        assert_eq!(players(&leaderboards, "arena"), vec![(1, "U-zkxs".to_string(), 99.0), (2, "zkxs".to_string(), 10.0)]);
+        assert_eq!(leaderboards.rank("arena", &user_id).unwrap().score.score, 10.0);
+        leaderboards.remove("arena", &name("U-zkxs")).await.unwrap();
+        assert_eq!(leaderboards.rank("arena", &user_id).unwrap().rank, 1);
+
+        // and the two stay apart across a reload
+        leaderboards.submit("arena", score("U-zkxs", 5.0, 2), None, None).await.unwrap();
+        let reloaded = Leaderboards::load(temp_file.path()).unwrap();
+        assert_eq!(reloaded.rank("arena", &user_id).unwrap().score.score, 10.0);
+        assert_eq!(reloaded.rank("arena", &name("U-zkxs")).unwrap().score.score, 5.0);
+    }
+
+    #[tokio::test]
+    async fn removing_and_clearing() {
+        let temp_file = TempFile::new("leaderboard-removing");
+        let leaderboards = Leaderboards::load(temp_file.path()).unwrap();
+        leaderboards.submit("arena", score("a", 1.0, 0), Some(ScoreOrder::Lower), None).await.unwrap();
+        leaderboards.submit("arena", score("b", 2.0, 1), None, None).await.unwrap();
+        assert_eq!(leaderboards.remove("arena", &name("a")).await.unwrap().rank, 1);
+        assert!(matches!(leaderboards.remove("arena", &name("a")).await, Err(Error::ScoreNotFound(_))));
+        assert_eq!(leaderboards.clear("arena").await.unwrap(), 1);
+        assert_eq!(leaderboards.clear("arena").await.unwrap(), 0);
+
+        // an emptied board is forgotten along with its options
+        leaderboards.submit("arena", score("c", 1.0, 2), Some(ScoreOrder::Higher), None).await.unwrap();
+        let reloaded = Leaderboards::load(temp_file.path()).unwrap();
+        assert_eq!(players(&reloaded, "arena"), vec![(1, "c".to_string(), 1.0)]);
+    }
+}
@@ -0,0 +1,30 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub use crate::server::ServerBuilder;
+
+pub mod client;
+pub mod dto;
+pub mod duration_format;
+pub mod error;
+pub mod json_file;
+pub mod kv_store;
+pub mod leaderboard;
+pub mod names;
+pub mod neos_api_url;
+pub mod notification;
+pub mod output_format;
+pub mod pagination;
+pub mod rich_text;
+pub mod rooms;
+pub mod sanitize;
+pub mod session_feed;
+pub mod session_list;
+pub mod session_poller;
+pub mod settings;
+pub mod single_flight;
+pub mod template;
+pub mod timers;
+pub mod user_cache;
+mod handlers;
+mod server;
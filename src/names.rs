@@ -0,0 +1,27 @@
+use crate::error::Error;
+
+/// longest name a client can give to a key-value store namespace or key, a timer, a room, a leaderboard or a player,
+/// in characters
+pub const MAX_NAME_LENGTH: usize = 128;
+
+/// Check a client-chosen name, describing it as `kind` in the error
+pub fn check_name(kind: &str, name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        Err(Error::InvalidQuery(format!("{} must be between 1 and {} characters", kind, MAX_NAME_LENGTH)))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(check_name("room name", "lobby").is_ok());
+        assert!(check_name("room name", &"å".repeat(MAX_NAME_LENGTH)).is_ok());
+        assert!(check_name("room name", &"a".repeat(MAX_NAME_LENGTH + 1)).is_err());
+        assert_eq!(check_name("room name", "").unwrap_err().to_string(), "invalid request: room name must be between 1 and 128 characters");
+    }
+}
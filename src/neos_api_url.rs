@@ -0,0 +1,127 @@
+use std::fmt;
+use std::str::FromStr;
+
+use hyper::Uri;
+
+/// Base URL of the Neos cloud API, including any path prefix, e.g. `https://www.neosvr-api.com/api` or
+/// `http://localhost:8080/mock/api`. Every upstream request URI is derived from this.
+#[derive(Clone)]
+pub struct NeosApiUrl {
+    /// `scheme://authority/prefix`, always without a trailing slash
+    base: String,
+}
+
+impl NeosApiUrl {
+    /// `GET {base}/sessions`
+    pub fn sessions(&self) -> Uri {
+        self.join("sessions")
+    }
+
+    /// `GET {base}/users/{user_id}`
+    pub fn user(&self, user_id: &str) -> Uri {
+        self.join(&format!("users/{}", percent_encode(user_id)))
+    }
+
+    /// `GET {base}/users?name={name}`
+    pub fn user_search(&self, name: &str) -> Uri {
+        self.join(&format!("users?name={}", percent_encode(name)))
+    }
+
+    /// `GET {base}/stats/onlineUserStats`
+    pub fn online_stats(&self) -> Uri {
+        self.join("stats/onlineUserStats")
+    }
+
+    fn join(&self, path: &str) -> Uri {
+        // the base was validated when it was parsed and the path is either static or escaped, so this cannot fail
+        format!("{}/{}", self.base, path).parse().expect("Neos API URI should always be valid")
+    }
+}
+
+impl FromStr for NeosApiUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let uri = s.parse::<Uri>()
+            .map_err(|e| format!("invalid Neos API base URL \"{}\": {}", s, e))?;
+        match uri.scheme_str() {
+            Some("http") | Some("https") => (),
+            Some(scheme) => return Err(format!("invalid Neos API base URL \"{}\": unsupported scheme \"{}\", expected http or https", s, scheme)),
+            None => return Err(format!("invalid Neos API base URL \"{}\": missing scheme, expected http:// or https://", s)),
+        }
+        let authority = match uri.authority() {
+            Some(authority) => authority,
+            None => return Err(format!("invalid Neos API base URL \"{}\": missing host", s)),
+        };
+        if uri.query().is_some() {
+            return Err(format!("invalid Neos API base URL \"{}\": query strings are not supported", s));
+        }
+        let scheme = uri.scheme_str().expect("scheme was checked above");
+        let prefix = uri.path().trim_end_matches('/');
+        Ok(NeosApiUrl {
+            base: format!("{}://{}{}", scheme, authority, prefix),
+        })
+    }
+}
+
+impl fmt::Display for NeosApiUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.base)
+    }
+}
+
+// escape everything outside of the RFC 3986 unreserved set, which is safe in both path segments and query values
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(base: &str) -> NeosApiUrl {
+        base.parse().unwrap()
+    }
+
+    #[test]
+    fn base_urls() {
+        assert_eq!(url("https://www.neosvr-api.com/api").to_string(), "https://www.neosvr-api.com/api");
+        assert_eq!(url("http://localhost:8080/mock/api/").to_string(), "http://localhost:8080/mock/api");
+        assert_eq!(url("http://localhost:8080").to_string(), "http://localhost:8080");
+        assert_eq!(url("http://localhost:8080//").to_string(), "http://localhost:8080");
+    }
+
+    #[test]
+    fn invalid_base_urls() {
+        let error = |base: &str| base.parse::<NeosApiUrl>().err().unwrap();
+        assert!(error("ftp://example.com/api").ends_with("unsupported scheme \"ftp\", expected http or https"));
+        assert!(error("/api").ends_with("missing scheme, expected http:// or https://"));
+        assert!(error("https://example.com/api?key=1").ends_with("query strings are not supported"));
+        assert!(error("https://exa mple.com").starts_with("invalid Neos API base URL \"https://exa mple.com\""));
+    }
+
+    #[test]
+    fn request_uris() {
+        let base = url("http://localhost:8080/mock/api/");
+        assert_eq!(base.sessions().to_string(), "http://localhost:8080/mock/api/sessions");
+        assert_eq!(base.online_stats().to_string(), "http://localhost:8080/mock/api/stats/onlineUserStats");
+        assert_eq!(base.user("U-zkxs").to_string(), "http://localhost:8080/mock/api/users/U-zkxs");
+        assert_eq!(base.user("U-a/../b").to_string(), "http://localhost:8080/mock/api/users/U-a%2F..%2Fb");
+        assert_eq!(base.user_search("a b&c=d").to_string(), "http://localhost:8080/mock/api/users?name=a%20b%26c%3Dd");
+    }
+
+    #[test]
+    fn percent_encoding() {
+        assert_eq!(percent_encode("Az09-._~"), "Az09-._~");
+        assert_eq!(percent_encode("a b?#%+"), "a%20b%3F%23%25%2B");
+        assert_eq!(percent_encode("ü"), "%C3%BC");
+        assert_eq!(percent_encode(""), "");
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// forget a client that has not polled for this long
+pub const DEFAULT_CLIENT_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// Tracks which sessions each client has already been shown in each view of a session list, so every client gets its own
+/// "new session" notification. A view is a list together with the per-request filter applied to it, so a client
+/// polling one list with different filters does not see the sessions it switches between as new.
+pub struct NotificationTracker {
+    /// keyed by view and client identity
+    clients: Mutex<HashMap<(String, String), ClientState>>,
+    expiry: Duration,
+}
+
+struct ClientState {
+    seen_sessions: HashSet<String>,
+    last_poll: Instant,
+}
+
+impl NotificationTracker {
+    pub fn new(expiry: Duration) -> Self {
+        NotificationTracker {
+            clients: Mutex::new(HashMap::new()),
+            expiry,
+        }
+    }
+
+    /// Record the sessions of a view that a client is being shown. Returns those that were not shown to that client on
+    /// its previous poll of the same view; a notification is needed if this is not empty.
+    pub fn update(&self, view: &str, client: &str, sessions: HashSet<String>) -> HashSet<String> {
+        self.update_at(view, client, sessions, Instant::now())
+    }
+
+    fn update_at(&self, view: &str, client: &str, sessions: HashSet<String>, now: Instant) -> HashSet<String> {
+        let key = (view.to_string(), client.to_string());
+        let mut clients = self.clients.lock().expect("notification tracker poisoned");
+        clients.retain(|_, state| now.duration_since(state.last_poll) <= self.expiry);
+
+        let new_sessions = match clients.get(&key) {
+            Some(state) => sessions.difference(&state.seen_sessions).cloned().collect(),
+            None => sessions.clone(),
+        };
+        clients.insert(key, ClientState {
+            seen_sessions: sessions,
+            last_poll: now,
+        });
+        new_sessions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions(ids: &[&str]) -> HashSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn first_and_repeat_sightings() {
+        let tracker = NotificationTracker::new(DEFAULT_CLIENT_EXPIRY);
+        assert_eq!(tracker.update("default", "a", sessions(&["S-1", "S-2"])), sessions(&["S-1", "S-2"]));
+        assert!(tracker.update("default", "a", sessions(&["S-1", "S-2"])).is_empty());
+        assert_eq!(tracker.update("default", "a", sessions(&["S-2", "S-3"])), sessions(&["S-3"]));
+        // only the previous poll counts, so a session that comes back is new again
+        assert_eq!(tracker.update("default", "a", sessions(&["S-1", "S-3"])), sessions(&["S-1"]));
+    }
+
+    #[test]
+    fn clients_and_lists_are_separate() {
+        let tracker = NotificationTracker::new(DEFAULT_CLIENT_EXPIRY);
+        tracker.update("default", "a", sessions(&["S-1"]));
+        assert_eq!(tracker.update("default", "b", sessions(&["S-1"])), sessions(&["S-1"]));
+        assert_eq!(tracker.update("events", "a", sessions(&["S-1"])), sessions(&["S-1"]));
+        assert!(tracker.update("default", "a", sessions(&["S-1"])).is_empty());
+    }
+
+    #[test]
+    fn idle_clients_expire() {
+        let tracker = NotificationTracker::new(Duration::from_secs(60));
+        let start = Instant::now();
+        tracker.update_at("default", "a", sessions(&["S-1"]), start);
+        tracker.update_at("default", "b", sessions(&["S-1"]), start);
+        assert!(tracker.update_at("default", "a", sessions(&["S-1"]), start + Duration::from_secs(60)).is_empty());
+        // a's poll kept it around, but b was forgotten and sees everything as new
+        assert_eq!(tracker.update_at("default", "b", sessions(&["S-1"]), start + Duration::from_secs(61)), sessions(&["S-1"]));
+        assert!(tracker.update_at("default", "a", sessions(&["S-1"]), start + Duration::from_secs(61)).is_empty());
+    }
+}
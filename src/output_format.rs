@@ -0,0 +1,113 @@
+use crate::error::Error;
+
+/// How a route renders its response body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// the Logix-friendly plain text each route has always returned
+    Text,
+    /// a typed JSON document with the same information
+    Json,
+}
+
+impl OutputFormat {
+    /// Pick a format from the `format` query parameter, falling back to the `Accept` header. Text is the default, so
+    /// Logix clients that send no `Accept` header (or `*/*`) keep working unchanged.
+    pub fn negotiate(format: Option<&str>, accept: Option<&str>) -> Result<Self, Error> {
+        match format {
+            Some("json") => Ok(OutputFormat::Json),
+            Some("text") => Ok(OutputFormat::Text),
+            Some(other) => Err(Error::InvalidQuery(format!("unknown format \"{}\", expected \"text\" or \"json\"", other))),
+            None => Ok(accept.map(OutputFormat::from_accept).unwrap_or(OutputFormat::Text)),
+        }
+    }
+
+    /// JSON only if the client prefers `application/json` over plain text. Equal preferences go to whichever type is
+    /// listed first, and wildcards leave the choice to the server.
+    fn from_accept(accept: &str) -> Self {
+        let mut json: Option<(f32, usize)> = None;
+        let mut text: Option<(f32, usize)> = None;
+        for (position, media_range) in accept.split(',').enumerate() {
+            let mut parameters = media_range.split(';').map(str::trim);
+            let media_type = parameters.next().unwrap_or("").to_ascii_lowercase();
+            let quality = parameters
+                .filter_map(|parameter| parameter.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let preference = match media_type.as_str() {
+                "application/json" => &mut json,
+                "text/plain" | "text/*" => &mut text,
+                _ => continue,
+            };
+            if preference.is_none_or(|(best, _)| quality > best) {
+                *preference = Some((quality, position));
+            }
+        }
+        match (json, text) {
+            (Some((json_quality, _)), _) if json_quality <= 0.0 => OutputFormat::Text,
+            (Some(_), None) => OutputFormat::Json,
+            (Some((json_quality, json_position)), Some((text_quality, text_position))) => {
+                if json_quality > text_quality || (json_quality == text_quality && json_position < text_position) {
+                    OutputFormat::Json
+                } else {
+                    OutputFormat::Text
+                }
+            }
+            (None, _) => OutputFormat::Text,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept(accept: &str) -> OutputFormat {
+        OutputFormat::from_accept(accept)
+    }
+
+    #[test]
+    fn format_parameter_wins() {
+        assert_eq!(OutputFormat::negotiate(Some("json"), Some("text/plain")).unwrap(), OutputFormat::Json);
+        assert_eq!(OutputFormat::negotiate(Some("text"), Some("application/json")).unwrap(), OutputFormat::Text);
+        assert_eq!(OutputFormat::negotiate(None, None).unwrap(), OutputFormat::Text);
+        assert!(matches!(OutputFormat::negotiate(Some("xml"), None), Err(Error::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn accept_headers() {
+        assert_eq!(accept("application/json"), OutputFormat::Json);
+        assert_eq!(accept("Application/JSON; charset=utf-8"), OutputFormat::Json);
+        assert_eq!(accept("text/plain"), OutputFormat::Text);
+        assert_eq!(accept("text/html, image/png"), OutputFormat::Text);
+        assert_eq!(accept(""), OutputFormat::Text);
+    }
+
+    #[test]
+    fn wildcards_leave_the_choice_to_the_server() {
+        assert_eq!(accept("*/*"), OutputFormat::Text);
+        assert_eq!(accept("application/*"), OutputFormat::Text);
+        assert_eq!(accept("application/json, */*"), OutputFormat::Json);
+        assert_eq!(accept("text/*, application/json"), OutputFormat::Text);
+        assert_eq!(accept("text/*;q=0.5, application/json"), OutputFormat::Json);
+    }
+
+    #[test]
+    fn quality_values() {
+        assert_eq!(accept("text/plain;q=0.5, application/json;q=0.9"), OutputFormat::Json);
+        assert_eq!(accept("application/json;q=0.5, text/plain"), OutputFormat::Text);
+        // equal preferences go to whichever came first
+        assert_eq!(accept("application/json;q=0.8, text/plain;q=0.8"), OutputFormat::Json);
+        assert_eq!(accept("text/plain;q=0.8, application/json;q=0.8"), OutputFormat::Text);
+        // a type listed twice counts at its best
+        assert_eq!(accept("application/json;q=0.1, text/plain;q=0.5, application/json"), OutputFormat::Json);
+        // an unparseable quality counts as 1
+        assert_eq!(accept("text/plain;q=0.5, application/json;q=high"), OutputFormat::Json);
+    }
+
+    #[test]
+    fn zero_quality_refuses_a_type() {
+        assert_eq!(accept("application/json;q=0"), OutputFormat::Text);
+        assert_eq!(accept("application/json;q=0, text/plain;q=0"), OutputFormat::Text);
+        assert_eq!(accept("text/plain;q=0, application/json;q=0.1"), OutputFormat::Json);
+    }
+}
@@ -0,0 +1,131 @@
+use serde::Serialize;
+
+use crate::error::Error;
+
+/// most items a single page can ask for
+pub const MAX_LIMIT: usize = 500;
+
+/// Which slice of a list route's items to return, from the `offset`, `limit` and `footer` query parameters
+#[derive(Clone, Copy, Default)]
+pub struct Page {
+    pub offset: usize,
+    /// `None` returns everything after `offset`
+    pub limit: Option<usize>,
+    /// end text responses with a line reporting the total and whether more pages exist, for clients that cannot read
+    /// response headers (such as Logix)
+    pub footer: bool,
+}
+
+/// Where a page sits in the full list
+#[derive(Serialize, Clone, Copy)]
+pub struct PageInfo {
+    pub total: usize,
+    pub offset: usize,
+    pub has_more: bool,
+}
+
+impl Page {
+    pub fn parse(offset: Option<&str>, limit: Option<&str>, footer: Option<&str>) -> Result<Self, Error> {
+        let offset = match offset {
+            Some(offset) => offset.parse::<usize>()
+                .map_err(|e| Error::InvalidQuery(format!("invalid value \"{}\" for offset: {}", offset, e)))?,
+            None => 0,
+        };
+        let limit = match limit {
+            Some(limit) => match limit.parse::<usize>() {
+                Ok(limit) if limit == 0 || limit > MAX_LIMIT => {
+                    return Err(Error::InvalidQuery(format!("limit must be between 1 and {}", MAX_LIMIT)));
+                }
+                Ok(limit) => Some(limit),
+                Err(e) => return Err(Error::InvalidQuery(format!("invalid value \"{}\" for limit: {}", limit, e))),
+            },
+            None => None,
+        };
+        let footer = match footer {
+            Some(footer) => footer.parse::<bool>()
+                .map_err(|e| Error::InvalidQuery(format!("invalid value \"{}\" for footer: {}", footer, e)))?,
+            None => false,
+        };
+        Ok(Page { offset, limit, footer })
+    }
+
+    /// Cut this page out of the full list
+    pub fn apply<T>(&self, items: Vec<T>) -> (Vec<T>, PageInfo) {
+        let total = items.len();
+        let page = items.into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect::<Vec<T>>();
+        let info = PageInfo {
+            total,
+            offset: self.offset,
+            has_more: self.offset.saturating_add(page.len()) < total,
+        };
+        (page, info)
+    }
+}
+
+impl PageInfo {
+    /// the trailing line added to text responses when a footer is requested, e.g. `total=74 more=true`
+    pub fn footer(&self) -> String {
+        format!("total={} more={}", self.total, self.has_more)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(offset: Option<&str>, limit: Option<&str>) -> Page {
+        Page::parse(offset, limit, None).unwrap()
+    }
+
+    fn error(offset: Option<&str>, limit: Option<&str>, footer: Option<&str>) -> String {
+        Page::parse(offset, limit, footer).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn parsing() {
+        let default = page(None, None);
+        assert_eq!((default.offset, default.limit, default.footer), (0, None, false));
+        let page = Page::parse(Some("20"), Some("10"), Some("true")).unwrap();
+        assert_eq!((page.offset, page.limit, page.footer), (20, Some(10), true));
+        assert_eq!(Page::parse(None, Some(&MAX_LIMIT.to_string()), None).unwrap().limit, Some(MAX_LIMIT));
+    }
+
+    #[test]
+    fn invalid_pages() {
+        assert_eq!(error(None, Some("0"), None), "invalid request: limit must be between 1 and 500");
+        assert_eq!(error(None, Some("501"), None), "invalid request: limit must be between 1 and 500");
+        assert!(error(None, Some("ten"), None).starts_with("invalid request: invalid value \"ten\" for limit"));
+        assert!(error(Some("-1"), None, None).starts_with("invalid request: invalid value \"-1\" for offset"));
+        assert!(error(None, None, Some("yes")).starts_with("invalid request: invalid value \"yes\" for footer"));
+    }
+
+    #[test]
+    fn slicing() {
+        let items = (1..=5).collect::<Vec<i32>>();
+        let (items_page, info) = page(Some("1"), Some("2")).apply(items.clone());
+        assert_eq!(items_page, vec![2, 3]);
+        assert_eq!((info.total, info.offset, info.has_more), (5, 1, true));
+
+        let (items_page, info) = page(Some("3"), Some("2")).apply(items.clone());
+        assert_eq!(items_page, vec![4, 5]);
+        assert!(!info.has_more);
+
+        let (items_page, info) = page(Some("2"), None).apply(items.clone());
+        assert_eq!(items_page, vec![3, 4, 5]);
+        assert!(!info.has_more);
+
+        // an offset past the end is an empty last page rather than an error
+        let (items_page, info) = page(Some("99"), Some("2")).apply(items);
+        assert!(items_page.is_empty());
+        assert_eq!((info.total, info.offset, info.has_more), (5, 99, false));
+    }
+
+    #[test]
+    fn footers() {
+        assert_eq!(PageInfo { total: 74, offset: 0, has_more: true }.footer(), "total=74 more=true");
+        assert_eq!(PageInfo { total: 0, offset: 10, has_more: false }.footer(), "total=0 more=false");
+    }
+}
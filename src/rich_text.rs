@@ -0,0 +1,248 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::dto::response_dto::SessionListEntry;
+
+/// When a color rule applies to a session list line, and how the line is styled if it does
+#[derive(Clone)]
+pub struct ColorRule {
+    pub condition: RuleCondition,
+    pub style: LineStyle,
+}
+
+/// Every set condition must hold for a rule to apply. A rule with no conditions applies to every line.
+#[derive(Clone, Default)]
+pub struct RuleCondition {
+    /// the session is new to this client: it appeared since the client's last `/sessionlist` poll, or is being
+    /// announced by a `session_added` feed event
+    pub new_session: Option<bool>,
+    /// the host's account is younger than this many days
+    pub host_account_younger_than_days: Option<i64>,
+    pub patron: Option<bool>,
+    /// the host has not been in the session for at least this many seconds
+    pub host_absent_for_secs: Option<i64>,
+}
+
+/// Neos rich-text styling for a whole line. Unset parts leave the line as it is.
+#[derive(Clone, Default)]
+pub struct LineStyle {
+    /// a color name such as `red`, or `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`
+    pub color: Option<String>,
+    pub bold: Option<bool>,
+    /// a `<size>` value such as `120%`
+    pub size: Option<String>,
+}
+
+/// Ordered color rules. Where several rules match a line, each part of the style comes from the first matching rule
+/// that sets it.
+#[derive(Clone)]
+pub struct ColorRules {
+    rules: Vec<ColorRule>,
+}
+
+/// A color rule as written in the settings file under `[[color_rules]]`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColorRuleConfig {
+    new_session: Option<bool>,
+    host_account_younger_than_days: Option<i64>,
+    patron: Option<bool>,
+    host_absent_for_secs: Option<i64>,
+    color: Option<String>,
+    bold: Option<bool>,
+    size: Option<String>,
+}
+
+impl RuleCondition {
+    fn matches(&self, entry: &SessionListEntry, now: DateTime<Utc>) -> bool {
+        let host_age_days = entry.host.as_ref().map(|host| now.signed_duration_since(host.registration_date).num_days());
+        // a host with no recorded departure time counts as absent for as long as we can tell
+        let host_absent_secs = if entry.host_present {
+            None
+        } else {
+            Some(entry.away_since.map_or(i64::MAX, |away_since| now.signed_duration_since(away_since).num_seconds()))
+        };
+        self.new_session.is_none_or(|new_session| entry.is_new == new_session)
+            && self.host_account_younger_than_days.is_none_or(|days| host_age_days.is_some_and(|age| age < days))
+            && self.patron.is_none_or(|patron| entry.host.as_ref().is_some_and(|host| host.is_patron == patron))
+            && self.host_absent_for_secs.is_none_or(|secs| host_absent_secs.is_some_and(|absent| absent >= secs))
+    }
+}
+
+impl ColorRules {
+    pub fn from_config(config: Vec<ColorRuleConfig>) -> Result<Self, String> {
+        let mut rules = Vec::with_capacity(config.len());
+        for (index, rule) in config.into_iter().enumerate() {
+            if let Some(color) = rule.color.as_deref() {
+                if !is_valid_color(color) {
+                    return Err(format!("color rule {} has invalid color \"{}\"", index + 1, color));
+                }
+            }
+            if let Some(size) = rule.size.as_deref() {
+                if size.is_empty() || !size.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '%' | '.' | '+' | '-')) {
+                    return Err(format!("color rule {} has invalid size \"{}\"", index + 1, size));
+                }
+            }
+            rules.push(ColorRule {
+                condition: RuleCondition {
+                    new_session: rule.new_session,
+                    host_account_younger_than_days: rule.host_account_younger_than_days,
+                    patron: rule.patron,
+                    host_absent_for_secs: rule.host_absent_for_secs,
+                },
+                style: LineStyle {
+                    color: rule.color,
+                    bold: rule.bold,
+                    size: rule.size,
+                },
+            });
+        }
+        Ok(ColorRules { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Wrap a rendered line in the rich-text tags of every rule that matches its session
+    pub fn apply(&self, line: String, entry: &SessionListEntry, now: DateTime<Utc>) -> String {
+        let mut style = LineStyle::default();
+        for rule in self.rules.iter().filter(|rule| rule.condition.matches(entry, now)) {
+            style.color = style.color.or_else(|| rule.style.color.clone());
+            style.bold = style.bold.or(rule.style.bold);
+            style.size = style.size.or_else(|| rule.style.size.clone());
+        }
+        let mut line = line;
+        if style.bold == Some(true) {
+            line = format!("<b>{}</b>", line);
+        }
+        if let Some(size) = style.size {
+            line = format!("<size={}>{}</size>", size, line);
+        }
+        if let Some(color) = style.color {
+            line = format!("<color={}>{}</color>", color, line);
+        }
+        line
+    }
+}
+
+/// highlight what mentors most want to notice: new sessions, brand new accounts, abandoned sessions and patrons
+impl Default for ColorRules {
+    fn default() -> Self {
+        let rule = |condition: RuleCondition, color: Option<&str>, bold: Option<bool>| ColorRule {
+            condition,
+            style: LineStyle {
+                color: color.map(str::to_string),
+                bold,
+                size: None,
+            },
+        };
+        ColorRules {
+            rules: vec![
+                rule(RuleCondition { new_session: Some(true), ..RuleCondition::default() }, None, Some(true)),
+                rule(RuleCondition { host_account_younger_than_days: Some(1), ..RuleCondition::default() }, Some("#ff6060"), None),
+                rule(RuleCondition { host_absent_for_secs: Some(5 * 60), ..RuleCondition::default() }, Some("#a0a0a0"), None),
+                rule(RuleCondition { patron: Some(true), ..RuleCondition::default() }, Some("#ffc040"), None),
+            ],
+        }
+    }
+}
+
+fn is_valid_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => !color.is_empty() && color.chars().all(|c| c.is_ascii_alphabetic()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+    use crate::dto::response_dto::SessionHost;
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2022, 6, 1).and_hms(12, 0, 0)
+    }
+
+    fn entry(is_new: bool, host_age_days: Option<i64>, is_patron: bool, host_absent_secs: Option<i64>) -> SessionListEntry {
+        SessionListEntry {
+            session_id: "S-a".to_string(),
+            name: "world".to_string(),
+            host_username: "host".to_string(),
+            host_user_id: Some("U-host".to_string()),
+            active_users: 1,
+            joined_users: 1,
+            session_begin_time: now() - Duration::hours(1),
+            uptime_secs: 3600,
+            host_present: host_absent_secs.is_none(),
+            away_since: host_absent_secs.map(|secs| now() - Duration::seconds(secs)),
+            is_new,
+            host: host_age_days.map(|days| SessionHost {
+                registration_date: now() - Duration::days(days),
+                is_patron,
+            }),
+            host_error: None,
+            new_users: None,
+        }
+    }
+
+    fn rules(config: &str) -> Result<ColorRules, String> {
+        #[derive(Deserialize)]
+        struct Settings {
+            color_rules: Vec<ColorRuleConfig>,
+        }
+        ColorRules::from_config(toml::from_str::<Settings>(config).unwrap().color_rules)
+    }
+
+    #[test]
+    fn default_rules() {
+        let rules = ColorRules::default();
+        let style = |entry: SessionListEntry| rules.apply("line".to_string(), &entry, now());
+        assert_eq!(style(entry(false, Some(100), false, None)), "line");
+        assert_eq!(style(entry(true, Some(100), false, None)), "<b>line</b>");
+        assert_eq!(style(entry(false, Some(0), false, None)), "<color=#ff6060>line</color>");
+        assert_eq!(style(entry(false, Some(100), false, Some(300))), "<color=#a0a0a0>line</color>");
+        assert_eq!(style(entry(false, Some(100), false, Some(299))), "line");
+        assert_eq!(style(entry(false, Some(100), true, None)), "<color=#ffc040>line</color>");
+        // the first rule that sets a color wins, while boldness comes from another rule
+        assert_eq!(style(entry(true, Some(0), true, None)), "<color=#ff6060><b>line</b></color>");
+    }
+
+    #[test]
+    fn unknown_hosts_match_no_host_conditions() {
+        let rules = ColorRules::default();
+        assert_eq!(rules.apply("line".to_string(), &entry(false, None, false, None), now()), "line");
+        // a host that left without a recorded time counts as long gone
+        let mut gone = entry(false, None, false, Some(0));
+        gone.away_since = None;
+        assert_eq!(rules.apply("line".to_string(), &gone, now()), "<color=#a0a0a0>line</color>");
+    }
+
+    #[test]
+    fn configured_rules() {
+        let rules = rules(r##"
+            [[color_rules]]
+            new_session = false
+            patron = true
+            size = "120%"
+            [[color_rules]]
+            color = "blue"
+        "##).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules.apply("line".to_string(), &entry(false, Some(5), true, None), now()), "<color=blue><size=120%>line</size></color>");
+        assert_eq!(rules.apply("line".to_string(), &entry(true, Some(5), true, None), now()), "<color=blue>line</color>");
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert_eq!(rules("[[color_rules]]\ncolor = \"#12345\"").err().unwrap(), "color rule 1 has invalid color \"#12345\"");
+        assert_eq!(rules("[[color_rules]]\n[[color_rules]]\nsize = \"1<b>\"").err().unwrap(), "color rule 2 has invalid size \"1<b>\"");
+        assert!(rules("[[color_rules]]\ncolor = \"#fff\"\n[[color_rules]]\ncolor = \"red\"").is_ok());
+    }
+}
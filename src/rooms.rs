@@ -0,0 +1,335 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+
+use crate::error::Error;
+use crate::names::check_name;
+
+/// longest message, in bytes
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+/// most members a room can have unless configured otherwise
+pub const DEFAULT_MAX_MEMBERS: usize = 32;
+/// how many recent messages each room keeps for members who join later, unless configured otherwise
+pub const DEFAULT_HISTORY: usize = 20;
+/// how long an empty room keeps its history before it is forgotten, unless configured otherwise
+pub const DEFAULT_HISTORY_EXPIRY: Duration = Duration::from_secs(60 * 60);
+/// how many events a slow member can fall behind by before it starts missing messages
+const EVENT_BUFFER: usize = 256;
+
+/// A message as it was published to a room
+#[derive(Clone)]
+pub struct RoomMessage {
+    /// the member that sent it, so that it is not sent back to them
+    sender: u64,
+    pub text: Arc<str>,
+    pub sent_at: DateTime<Utc>,
+}
+
+/// Something that happened in a room, as delivered to its members
+#[derive(Clone)]
+pub enum RoomEvent {
+    Message(RoomMessage),
+    /// someone joined or left, leaving this many members
+    Members(usize),
+}
+
+/// A room's occupancy, for clients deciding whether to join
+pub struct RoomStatus {
+    pub members: usize,
+    pub max_members: usize,
+    /// how many messages are kept for replay
+    pub retained: usize,
+}
+
+struct Room {
+    members: usize,
+    /// when the last member left, if the room is empty
+    empty_since: Option<Instant>,
+    max_members: usize,
+    history: VecDeque<RoomMessage>,
+    events: broadcast::Sender<RoomEvent>,
+}
+
+/// Named rooms where every message a member sends is relayed to the other members. Rooms spring into existence when
+/// the first member joins. Once empty, they are forgotten straight away if they have no history, and otherwise when
+/// their history expires.
+pub struct Rooms {
+    rooms: Mutex<HashMap<String, Room>>,
+    max_members: usize,
+    history: usize,
+    history_expiry: Duration,
+    next_member_id: AtomicU64,
+}
+
+/// One member's place in a room. Dropping it leaves the room.
+pub struct Membership {
+    rooms: Arc<Rooms>,
+    room: String,
+    id: u64,
+    events: broadcast::Receiver<RoomEvent>,
+}
+
+impl RoomEvent {
+    /// whether a member should be sent this event; members do not hear their own messages
+    pub fn is_for(&self, membership: &Membership) -> bool {
+        match self {
+            RoomEvent::Message(message) => message.sender != membership.id,
+            RoomEvent::Members(_) => true,
+        }
+    }
+}
+
+impl Rooms {
+    /// `max_members` is the most members any room can have, `history` how many messages each room keeps, and
+    /// `history_expiry` how long an empty room keeps them for
+    pub fn new(max_members: usize, history: usize, history_expiry: Duration) -> Self {
+        Rooms {
+            rooms: Mutex::new(HashMap::new()),
+            max_members,
+            history,
+            history_expiry,
+            next_member_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Join a room, returning the membership and up to `replay` of the room's most recent messages, oldest first.
+    ///
+    /// The first member to join an empty room can lower its member limit with `max_members`; later members get
+    /// whatever limit is in place. Everyone in the room, including the new member, is sent the new member count.
+    pub fn join(self: &Arc<Self>, name: &str, max_members: Option<usize>, replay: usize) -> Result<(Membership, Vec<RoomMessage>), Error> {
+        check_name("room name", name)?;
+        let max_members = match max_members {
+            Some(max_members) if max_members == 0 || max_members > self.max_members => {
+                return Err(Error::InvalidQuery(format!("max_members must be between 1 and {}", self.max_members)));
+            }
+            max_members => max_members.unwrap_or(self.max_members),
+        };
+        let mut rooms = self.rooms.lock().expect("rooms poisoned");
+        let now = Instant::now();
+        rooms.retain(|_, room| room.empty_since.is_none_or(|empty_since| now.duration_since(empty_since) <= self.history_expiry));
+        let room = rooms.entry(name.to_string()).or_insert_with(|| Room {
+            members: 0,
+            empty_since: None,
+            max_members,
+            history: VecDeque::new(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+        });
+        if room.members == 0 {
+            room.max_members = max_members;
+        } else if room.members >= room.max_members {
+            return Err(Error::RoomFull(name.to_string()));
+        }
+        room.members += 1;
+        room.empty_since = None;
+        let membership = Membership {
+            rooms: self.clone(),
+            room: name.to_string(),
+            id: self.next_member_id.fetch_add(1, Ordering::Relaxed),
+            events: room.events.subscribe(),
+        };
+        let history = room.history.iter()
+            .skip(room.history.len().saturating_sub(replay))
+            .cloned()
+            .collect();
+        // the new member is subscribed, so there is always a receiver
+        let _ = room.events.send(RoomEvent::Members(room.members));
+        Ok((membership, history))
+    }
+
+    pub fn status(&self, name: &str) -> Result<RoomStatus, Error> {
+        check_name("room name", name)?;
+        let rooms = self.rooms.lock().expect("rooms poisoned");
+        let now = Instant::now();
+        // an empty room's limit is reset by whoever joins it next, and its history may have expired already
+        Ok(match rooms.get(name) {
+            Some(room) if room.members > 0 => RoomStatus {
+                members: room.members,
+                max_members: room.max_members,
+                retained: room.history.len(),
+            },
+            room => RoomStatus {
+                members: 0,
+                max_members: self.max_members,
+                retained: room
+                    .filter(|room| room.empty_since.is_none_or(|empty_since| now.duration_since(empty_since) <= self.history_expiry))
+                    .map_or(0, |room| room.history.len()),
+            },
+        })
+    }
+
+    fn publish(&self, membership: &Membership, text: &str) -> Result<(), Error> {
+        if text.len() > MAX_MESSAGE_LENGTH {
+            return Err(Error::InvalidValue(format!("messages must be at most {} bytes", MAX_MESSAGE_LENGTH)));
+        }
+        let message = RoomMessage {
+            sender: membership.id,
+            text: Arc::from(text),
+            sent_at: Utc::now(),
+        };
+        let mut rooms = self.rooms.lock().expect("rooms poisoned");
+        let room = rooms.get_mut(&membership.room).expect("a member's room should exist");
+        if self.history > 0 {
+            if room.history.len() == self.history {
+                room.history.pop_front();
+            }
+            room.history.push_back(message.clone());
+        }
+        // sent under the lock, so every member sees messages in the same order as the history
+        let _ = room.events.send(RoomEvent::Message(message));
+        Ok(())
+    }
+
+    fn leave(&self, membership: &Membership) {
+        let mut rooms = self.rooms.lock().expect("rooms poisoned");
+        if let Some(room) = rooms.get_mut(&membership.room) {
+            room.members -= 1;
+            if room.members > 0 {
+                let _ = room.events.send(RoomEvent::Members(room.members));
+            } else if room.history.is_empty() {
+                rooms.remove(&membership.room);
+            } else {
+                room.empty_since = Some(Instant::now());
+            }
+        }
+    }
+}
+
+impl Default for Rooms {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MEMBERS, DEFAULT_HISTORY, DEFAULT_HISTORY_EXPIRY)
+    }
+}
+
+impl Membership {
+    pub fn room(&self) -> &str {
+        &self.room
+    }
+
+    /// Send a message to every other member of the room
+    pub fn publish(&self, text: &str) -> Result<(), Error> {
+        self.rooms.publish(self, text)
+    }
+
+    /// Wait for the next event in the room, including this member's own messages
+    pub async fn recv(&mut self) -> Result<RoomEvent, broadcast::error::RecvError> {
+        self.events.recv().await
+    }
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        self.rooms.leave(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rooms(max_members: usize, history: usize, history_expiry: Duration) -> Arc<Rooms> {
+        Arc::new(Rooms::new(max_members, history, history_expiry))
+    }
+
+    fn texts(messages: &[RoomMessage]) -> Vec<&str> {
+        messages.iter().map(|message| &*message.text).collect()
+    }
+
+    // the next event this member would be sent, if one is waiting
+    fn next_event(membership: &mut Membership) -> Option<RoomEvent> {
+        loop {
+            let event = membership.events.try_recv().ok()?;
+            if event.is_for(membership) {
+                return Some(event);
+            }
+        }
+    }
+
+    #[test]
+    fn messages_reach_the_other_members() {
+        let rooms = rooms(4, 10, DEFAULT_HISTORY_EXPIRY);
+        let (mut alice, _) = rooms.join("lobby", None, 0).unwrap();
+        assert!(matches!(next_event(&mut alice), Some(RoomEvent::Members(1))));
+        let (mut bob, _) = rooms.join("lobby", None, 0).unwrap();
+        assert!(matches!(next_event(&mut alice), Some(RoomEvent::Members(2))));
+        assert!(matches!(next_event(&mut bob), Some(RoomEvent::Members(2))));
+
+        alice.publish("hello").unwrap();
+        match next_event(&mut bob) {
+            Some(RoomEvent::Message(message)) => assert_eq!(&*message.text, "hello"),
+            _ => panic!("bob should hear alice"),
+        }
+        // nobody hears their own messages
+        assert!(next_event(&mut alice).is_none());
+
+        drop(bob);
+        assert!(matches!(next_event(&mut alice), Some(RoomEvent::Members(1))));
+        assert_eq!(rooms.status("lobby").unwrap().members, 1);
+    }
+
+    #[test]
+    fn member_limits() {
+        let rooms = rooms(4, 10, DEFAULT_HISTORY_EXPIRY);
+        assert!(matches!(rooms.join("lobby", Some(0), 0), Err(Error::InvalidQuery(_))));
+        assert!(matches!(rooms.join("lobby", Some(5), 0), Err(Error::InvalidQuery(_))));
+
+        // the first member sets the limit, and later members cannot change it
+        let first = rooms.join("lobby", Some(2), 0).unwrap();
+        let second = rooms.join("lobby", Some(4), 0).unwrap();
+        assert!(matches!(rooms.join("lobby", None, 0), Err(Error::RoomFull(_))));
+        assert_eq!(rooms.status("lobby").unwrap().max_members, 2);
+
+        // an emptied room takes the next first member's limit
+        drop(first);
+        drop(second);
+        assert_eq!(rooms.status("lobby").unwrap().max_members, 4);
+        let _members = [rooms.join("lobby", None, 0).unwrap(), rooms.join("lobby", None, 0).unwrap(), rooms.join("lobby", None, 0).unwrap()];
+        assert_eq!(rooms.status("lobby").unwrap().members, 3);
+    }
+
+    #[test]
+    fn history_replay() {
+        let rooms = rooms(4, 2, DEFAULT_HISTORY_EXPIRY);
+        let (alice, history) = rooms.join("lobby", None, 10).unwrap();
+        assert!(history.is_empty());
+        for text in ["one", "two", "three"] {
+            alice.publish(text).unwrap();
+        }
+        assert!(matches!(alice.publish(&"x".repeat(MAX_MESSAGE_LENGTH + 1)), Err(Error::InvalidValue(_))));
+
+        // only the most recent messages are kept, oldest first
+        assert_eq!(texts(&rooms.join("lobby", None, 10).unwrap().1), vec!["two", "three"]);
+        assert_eq!(texts(&rooms.join("lobby", None, 1).unwrap().1), vec!["three"]);
+        assert!(rooms.join("lobby", None, 0).unwrap().1.is_empty());
+        assert_eq!(rooms.status("lobby").unwrap().retained, 2);
+    }
+
+    #[test]
+    fn rooms_without_history_are_forgotten() {
+        let rooms = rooms(4, 0, DEFAULT_HISTORY_EXPIRY);
+        let (alice, _) = rooms.join("lobby", None, 10).unwrap();
+        alice.publish("hello").unwrap();
+        drop(alice);
+        assert!(rooms.rooms.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn history_expires_once_the_room_is_empty() {
+        let rooms = rooms(4, 10, Duration::from_millis(50));
+        let (alice, _) = rooms.join("lobby", None, 0).unwrap();
+        alice.publish("hello").unwrap();
+        drop(alice);
+        assert_eq!(rooms.status("lobby").unwrap().retained, 1);
+        assert_eq!(texts(&rooms.join("lobby", None, 10).unwrap().1), vec!["hello"]);
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(rooms.status("lobby").unwrap().retained, 0);
+        // joining any room forgets expired ones
+        rooms.join("other", None, 0).unwrap();
+        assert!(!rooms.rooms.lock().unwrap().contains_key("lobby"));
+        assert!(rooms.join("lobby", None, 10).unwrap().1.is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+
+use regex::Regex;
+
+lazy_static! {
+    /// Neos rich-text tags, such as `<color=red>`, `</size>` or `<b>`
+    static ref RICH_TEXT_TAG: Regex = Regex::new(r"</?[A-Za-z][A-Za-z0-9_-]*(=[^<>]*)?>").expect("rich text tag pattern should compile");
+}
+
+/// Which transformations are applied to upstream strings (usernames, world names) before they are written into a
+/// line-based text response. JSON responses are never sanitized, since JSON escaping already keeps them intact.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SanitizePolicy {
+    /// remove rich-text tags so names cannot restyle in-world text panels
+    pub strip_rich_text: bool,
+    /// prefix `\`, `(` and `)` with a backslash so names cannot be mistaken for the fields around them
+    pub escape_delimiters: bool,
+    /// replace control characters and line separators with a space so a name cannot start a new line
+    pub replace_control_characters: bool,
+    /// wrap every `<` left after stripping in `<noparse>`, so a partial tag cannot join the markup around the name.
+    /// Set for rich-text responses rather than chosen by name.
+    pub escape_rich_text: bool,
+}
+
+pub const STRIP_RICH_TEXT: &str = "strip_rich_text";
+pub const ESCAPE_DELIMITERS: &str = "escape_delimiters";
+pub const REPLACE_CONTROL_CHARACTERS: &str = "replace_control_characters";
+/// disables every policy
+pub const NONE: &str = "none";
+
+impl SanitizePolicy {
+    pub fn none() -> Self {
+        SanitizePolicy {
+            strip_rich_text: false,
+            escape_delimiters: false,
+            replace_control_characters: false,
+            escape_rich_text: false,
+        }
+    }
+
+    /// Build a policy from policy names, e.g. from `?sanitize=strip_rich_text,escape_delimiters`
+    pub fn from_names<'a>(names: impl IntoIterator<Item=&'a str>) -> Result<Self, String> {
+        let mut policy = SanitizePolicy::none();
+        for name in names {
+            match name {
+                STRIP_RICH_TEXT => policy.strip_rich_text = true,
+                ESCAPE_DELIMITERS => policy.escape_delimiters = true,
+                REPLACE_CONTROL_CHARACTERS => policy.replace_control_characters = true,
+                NONE => {}
+                other => return Err(format!(
+                    "unknown sanitization policy \"{}\", expected any of {}, {}, {} or {}",
+                    other, STRIP_RICH_TEXT, ESCAPE_DELIMITERS, REPLACE_CONTROL_CHARACTERS, NONE
+                )),
+            }
+        }
+        Ok(policy)
+    }
+
+    pub fn sanitize(&self, text: &str) -> String {
+        let mut text = if self.strip_rich_text {
+            strip_rich_text(text)
+        } else {
+            text.to_string()
+        };
+        if self.replace_control_characters {
+            text = text.chars()
+                .map(|c| if c.is_control() || c == '\u{2028}' || c == '\u{2029}' { ' ' } else { c })
+                .collect();
+        }
+        if self.escape_delimiters {
+            let mut escaped = String::with_capacity(text.len());
+            for c in text.chars() {
+                if matches!(c, '\\' | '(' | ')') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            text = escaped;
+        }
+        if self.escape_rich_text {
+            text = text.replace('<', "<noparse><</noparse>");
+        }
+        text
+    }
+}
+
+// Removing one tag can join the text around it into another, as in `<<b>color=red>`, so repeat until nothing is left.
+// Every pass that changes anything makes the text shorter, so this always finishes.
+fn strip_rich_text(text: &str) -> String {
+    let mut text = text.to_string();
+    while RICH_TEXT_TAG.is_match(&text) {
+        text = RICH_TEXT_TAG.replace_all(&text, "").into_owned();
+    }
+    text
+}
+
+/// strip rich text and replace control characters, which is safe for every existing Logix consumer
+impl Default for SanitizePolicy {
+    fn default() -> Self {
+        SanitizePolicy {
+            strip_rich_text: true,
+            escape_delimiters: false,
+            replace_control_characters: true,
+            escape_rich_text: false,
+        }
+    }
+}
+
+impl fmt::Display for SanitizePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (self.strip_rich_text, STRIP_RICH_TEXT),
+            (self.escape_delimiters, ESCAPE_DELIMITERS),
+            (self.replace_control_characters, REPLACE_CONTROL_CHARACTERS),
+        ].iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, name)| *name)
+            .collect::<Vec<&str>>();
+        if names.is_empty() {
+            write!(f, "{}", NONE)
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_rich_text() {
+        let policy = SanitizePolicy::from_names([STRIP_RICH_TEXT]).unwrap();
+        assert_eq!(policy.sanitize("<color=red>zkxs</color> <b>2</b>"), "zkxs 2");
+        assert_eq!(policy.sanitize("<<b>color=red>nested</color>"), "nested");
+        assert_eq!(policy.sanitize("<</b>/<i>b>>"), ">");
+        assert_eq!(policy.sanitize("1 < 2 > 0"), "1 < 2 > 0");
+    }
+
+    #[test]
+    fn replaces_control_characters_and_escapes_delimiters() {
+        let policy = SanitizePolicy::from_names([REPLACE_CONTROL_CHARACTERS, ESCAPE_DELIMITERS]).unwrap();
+        assert_eq!(policy.sanitize("a\nb\u{2028}c"), "a b c");
+        assert_eq!(policy.sanitize(r"(a\b)"), r"\(a\\b\)");
+        assert_eq!(policy.sanitize("<b>x</b>"), "<b>x</b>");
+    }
+
+    #[test]
+    fn escapes_stray_brackets_for_rich_text() {
+        let policy = SanitizePolicy { strip_rich_text: true, escape_rich_text: true, ..SanitizePolicy::none() };
+        assert_eq!(policy.sanitize("<b>zkxs</b> <color=red"), "zkxs <noparse><</noparse>color=red");
+        assert_eq!(policy.sanitize("1 < 2 > 0"), "1 <noparse><</noparse> 2 > 0");
+        assert_eq!(policy.sanitize("plain"), "plain");
+    }
+
+    #[test]
+    fn policy_names() {
+        assert!(SanitizePolicy::from_names([NONE]).unwrap() == SanitizePolicy::none());
+        assert!(matches!(SanitizePolicy::from_names(["bogus"]), Err(e) if e.contains("unknown sanitization policy \"bogus\"")));
+    }
+}
@@ -0,0 +1,1009 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{FutureExt, StreamExt};
+use serde::Deserialize;
+use warp::hyper::body::Bytes;
+use warp::Filter;
+
+use crate::client::{HyperNeosApi, NeosApi};
+use crate::duration_format::DurationFormat;
+use crate::error::Error;
+use crate::kv_store::KvStore;
+use crate::leaderboard::Leaderboards;
+use crate::handlers::*;
+use crate::notification::{self, NotificationTracker};
+use crate::output_format::OutputFormat;
+use crate::pagination::Page;
+use crate::rooms::{self, Rooms};
+use crate::sanitize::SanitizePolicy;
+use crate::session_list::{self, SessionLists};
+use crate::session_poller::{self, SessionPoller};
+use crate::settings::Settings;
+use crate::timers::{self, TimerOperation, Timers};
+use crate::user_cache::UserCache;
+
+/// Assembles the neos-api warp routes along with the state they share. The resulting filter can be served directly or
+/// mounted inside another warp server.
+///
+/// ```no_run
+/// # async fn run() {
+/// let routes = neos_api::ServerBuilder::new().build().expect("neos-api state should load");
+/// warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+/// # }
+/// ```
+pub struct ServerBuilder {
+    neos_api: Option<Arc<dyn NeosApi>>,
+    user_cache: Option<Arc<UserCache>>,
+    cache_file: Option<PathBuf>,
+    kv_store: Option<Arc<KvStore>>,
+    kv_store_file: Option<PathBuf>,
+    leaderboards: Option<Arc<Leaderboards>>,
+    leaderboard_file: Option<PathBuf>,
+    session_poll_interval: Duration,
+    session_max_age: Duration,
+    notification_expiry: Duration,
+    session_lists: SessionLists,
+    text_sanitization: SanitizePolicy,
+    uptime_format: DurationFormat,
+    room_max_members: usize,
+    room_history: usize,
+    room_history_expiry: Duration,
+    timer_expiry: Duration,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        ServerBuilder {
+            neos_api: None,
+            user_cache: None,
+            cache_file: None,
+            kv_store: None,
+            kv_store_file: None,
+            leaderboards: None,
+            leaderboard_file: None,
+            session_poll_interval: session_poller::DEFAULT_POLL_INTERVAL,
+            session_max_age: session_poller::DEFAULT_MAX_AGE,
+            notification_expiry: notification::DEFAULT_CLIENT_EXPIRY,
+            session_lists: SessionLists::default(),
+            text_sanitization: SanitizePolicy::default(),
+            uptime_format: DurationFormat::default(),
+            room_max_members: rooms::DEFAULT_MAX_MEMBERS,
+            room_history: rooms::DEFAULT_HISTORY,
+            room_history_expiry: rooms::DEFAULT_HISTORY_EXPIRY,
+            timer_expiry: timers::DEFAULT_EXPIRY,
+        }
+    }
+
+    /// Start from already loaded settings
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self::new()
+            .neos_api(Arc::new(HyperNeosApi::new(settings.neos_api_base_url.clone())))
+            .cache_file(settings.cache_file.clone())
+            .kv_store_file(settings.kv_store_file.clone())
+            .leaderboard_file(settings.leaderboard_file.clone())
+            .session_poll_interval(settings.session_poll_interval)
+            .session_max_age(settings.session_max_age)
+            .notification_expiry(settings.notification_expiry)
+            .session_lists(settings.session_lists.clone())
+            .text_sanitization(settings.text_sanitization)
+            .uptime_format(settings.uptime_format)
+            .room_max_members(settings.room_max_members)
+            .room_history(settings.room_history)
+            .room_history_expiry(settings.room_history_expiry)
+            .timer_expiry(settings.timer_expiry)
+    }
+
+    /// Use the given Neos API client instead of one pointed at the real service
+    pub fn neos_api(mut self, neos_api: Arc<dyn NeosApi>) -> Self {
+        self.neos_api = Some(neos_api);
+        self
+    }
+
+    /// Share an existing user cache instead of loading one from [`cache_file`](Self::cache_file)
+    pub fn user_cache(mut self, user_cache: Arc<UserCache>) -> Self {
+        self.user_cache = Some(user_cache);
+        self
+    }
+
+    /// Where the user cache is loaded from and persisted to
+    pub fn cache_file(mut self, cache_file: PathBuf) -> Self {
+        self.cache_file = Some(cache_file);
+        self
+    }
+
+    /// Share an existing key-value store instead of loading one from [`kv_store_file`](Self::kv_store_file)
+    pub fn kv_store(mut self, kv_store: Arc<KvStore>) -> Self {
+        self.kv_store = Some(kv_store);
+        self
+    }
+
+    /// Where the `/kv` store is loaded from and persisted to
+    pub fn kv_store_file(mut self, kv_store_file: PathBuf) -> Self {
+        self.kv_store_file = Some(kv_store_file);
+        self
+    }
+
+    /// Share existing leaderboards instead of loading them from [`leaderboard_file`](Self::leaderboard_file)
+    pub fn leaderboards(mut self, leaderboards: Arc<Leaderboards>) -> Self {
+        self.leaderboards = Some(leaderboards);
+        self
+    }
+
+    /// Where the `/leaderboard` boards are loaded from and persisted to
+    pub fn leaderboard_file(mut self, leaderboard_file: PathBuf) -> Self {
+        self.leaderboard_file = Some(leaderboard_file);
+        self
+    }
+
+    /// How often the session list is refreshed in the background
+    pub fn session_poll_interval(mut self, interval: Duration) -> Self {
+        self.session_poll_interval = interval;
+        self
+    }
+
+    /// Session lists older than this are refreshed before being served
+    pub fn session_max_age(mut self, max_age: Duration) -> Self {
+        self.session_max_age = max_age;
+        self
+    }
+
+    /// Forget a client's `/sessionlist` notification state after it has not polled for this long
+    pub fn notification_expiry(mut self, expiry: Duration) -> Self {
+        self.notification_expiry = expiry;
+        self
+    }
+
+    /// The named lists served at `/sessionlist/{name}`, including the default list served at `/sessionlist`
+    pub fn session_lists(mut self, session_lists: SessionLists) -> Self {
+        self.session_lists = session_lists;
+        self
+    }
+
+    /// How names are cleaned up in text responses when a request does not pass `?sanitize=`
+    pub fn text_sanitization(mut self, policy: SanitizePolicy) -> Self {
+        self.text_sanitization = policy;
+        self
+    }
+
+    /// How session uptime is written in text responses when a request does not pass `?uptime_format=`
+    pub fn uptime_format(mut self, uptime_format: DurationFormat) -> Self {
+        self.uptime_format = uptime_format;
+        self
+    }
+
+    /// The most members any `/room/{name}` can have
+    pub fn room_max_members(mut self, max_members: usize) -> Self {
+        self.room_max_members = max_members;
+        self
+    }
+
+    /// How many recent messages each room keeps for members who join later; zero keeps none
+    pub fn room_history(mut self, history: usize) -> Self {
+        self.room_history = history;
+        self
+    }
+
+    /// How long an empty room keeps its history before it is forgotten
+    pub fn room_history_expiry(mut self, expiry: Duration) -> Self {
+        self.room_history_expiry = expiry;
+        self
+    }
+
+    /// How long a `/timer/{name}` is kept once nothing has changed it and it is not counting down
+    pub fn timer_expiry(mut self, expiry: Duration) -> Self {
+        self.timer_expiry = expiry;
+        self
+    }
+
+    /// Compose every route and start the background session poller. Missing pieces of state fall back to the same
+    /// defaults the executable uses. Must be called from within a Tokio runtime.
+    ///
+    /// Fails with [`Error::Startup`] if a store has to be loaded and cannot be. A damaged store is never replaced by an
+    /// empty one, since that would lose its contents on the next save.
+    pub fn build(self) -> Result<impl Filter<Extract=(impl warp::Reply, ), Error=warp::Rejection> + Clone, Error> {
+        let neos_api: NeosApiDb = match self.neos_api {
+            Some(neos_api) => neos_api,
+            None => Arc::new(HyperNeosApi::new(Settings::default_neos_api_base_url())),
+        };
+        let user_cache_db: UserCacheDb = match (self.user_cache, self.cache_file) {
+            (Some(user_cache), _) => user_cache,
+            (None, Some(cache_file)) => Arc::new(UserCache::load(cache_file)),
+            (None, None) => Arc::new(UserCache::load(Settings::default_cache_file().map_err(Error::Startup)?)),
+        };
+        let kv_store_db: KvStoreDb = match (self.kv_store, self.kv_store_file) {
+            (Some(kv_store), _) => kv_store,
+            (None, kv_store_file) => {
+                let kv_store_file = kv_store_file.map_or_else(Settings::default_kv_store_file, Ok).map_err(Error::Startup)?;
+                let kv_store = KvStore::load(kv_store_file.clone())
+                    .map_err(|e| Error::Startup(format!("could not load key-value store {}: {}", kv_store_file.display(), e)))?;
+                Arc::new(kv_store)
+            }
+        };
+        let leaderboards_db: LeaderboardsDb = match (self.leaderboards, self.leaderboard_file) {
+            (Some(leaderboards), _) => leaderboards,
+            (None, leaderboard_file) => {
+                let leaderboard_file = leaderboard_file.map_or_else(Settings::default_leaderboard_file, Ok).map_err(Error::Startup)?;
+                let leaderboards = Leaderboards::load(leaderboard_file.clone())
+                    .map_err(|e| Error::Startup(format!("could not load leaderboards {}: {}", leaderboard_file.display(), e)))?;
+                Arc::new(leaderboards)
+            }
+        };
+
+        let session_lists_db: SessionListsDb = Arc::new(self.session_lists);
+        let notification_db: NotificationDb = Arc::new(NotificationTracker::new(self.notification_expiry));
+        let session_poller_db: SessionPollerDb = Arc::new(SessionPoller::new(neos_api.clone(), self.session_max_age));
+        session_poller_db.spawn(self.session_poll_interval);
+
+        let text_sanitization = self.text_sanitization;
+
+        // GET /hello/warp => 200 OK with body "Hello, warp!"
+        let hello = warp::path!("hello" / String)
+            .and(warp::get())
+            .and(output_format())
+            .and(sanitize_policy(text_sanitization))
+            .map(hello_handler);
+
+        // GET /hello => 200 OK with body "Hello!"
+        let hello_fallback = warp::path("hello")
+            .and(warp::get())
+            .and(output_format())
+            .map(hello_fallback_handler);
+
+        // POST /initTime "100" => 200 OK with body "100"
+        let init_time = warp::path("initTime")
+            .and(warp::post())
+            // Only accept bodies smaller than 16kb...
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(output_format())
+            .and(with_db(kv_store_db.clone()))
+            .and(warp::body::bytes())
+            .and_then(init_time_handler);
+
+        // POST /initTimeForce "100" => 200 OK with body "100"
+        let init_time_force = warp::path("initTimeForce")
+            .and(warp::post())
+            // Only accept bodies smaller than 16kb...
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(output_format())
+            .and(with_db(kv_store_db.clone()))
+            .and(warp::body::bytes())
+            .and_then(init_time_force_handler);
+
+        // POST /initTimeReset => 200 OK
+        let init_time_reset = warp::path("initTimeReset")
+            .and(warp::post())
+            .and(warp::body::content_length_limit(0))
+            .and(output_format())
+            .and(with_db(kv_store_db.clone()))
+            .and_then(init_time_reset_handler);
+
+        // GET /initTimePeek => 200 OK with body "Some(100)"
+        let init_time_peek = warp::path("initTimePeek")
+            .and(warp::get())
+            .and(output_format())
+            .and(with_db(kv_store_db.clone()))
+            .and_then(init_time_peek_handler);
+
+        // GET /counter => 200 OK with body "Some(0)"
+        let counter = warp::path("counter")
+            .and(warp::get())
+            .and(output_format())
+            .and(with_db(kv_store_db.clone()))
+            .and_then(counter_handler);
+
+        // GET /kv/world1/score => 200 OK with body "12"
+        let kv_get = warp::path!("kv" / String / String)
+            .and(warp::get())
+            .map(|namespace, key| (namespace, key, KvOperation::Get, Bytes::new()))
+            .untuple_one();
+
+        // DELETE /kv/world1/score => 200 OK with the removed value "12"
+        let kv_delete = warp::path!("kv" / String / String)
+            .and(warp::delete())
+            .map(|namespace, key| (namespace, key, KvOperation::Delete, Bytes::new()))
+            .untuple_one();
+
+        // POST /kv/world1/score "12" => 200 OK with body "12"
+        // POST /kv/world1/score/incr "" => 200 OK with body "13"
+        let kv_post = warp::path!("kv" / String / String)
+            .map(|namespace, key| (namespace, key, KvOperation::Set))
+            .untuple_one()
+            .or(warp::path!("kv" / String / String / KvOperation))
+            .unify()
+            .and(warp::post())
+            // Only accept bodies smaller than 16kb...
+            .and(optional_body(1024 * 16));
+
+        let kv = kv_get
+            .or(kv_delete)
+            .unify()
+            .or(kv_post)
+            .unify()
+            .and(output_format())
+            .and(warp::query::<KvQuery>())
+            .and(with_db(kv_store_db))
+            .and_then(kv_handler);
+
+        let timers_db: TimersDb = Arc::new(Timers::new(self.timer_expiry));
+
+        // GET /timer/countdown => 200 OK with body "running 12345 47655"
+        // POST /timer/countdown/start?duration_ms=60000 => the same, after starting it if it was stopped
+        let timer = warp::path!("timer" / String)
+            .and(warp::get())
+            .map(|name| (name, TimerOperation::Get))
+            .untuple_one()
+            .or(warp::path!("timer" / String / TimerOperation).and(warp::post()))
+            .unify()
+            .and(output_format())
+            .and(warp::query::<TimerQuery>())
+            .and(with_db(timers_db.clone()))
+            .and_then(timer_handler);
+
+        // WEBSOCKET /timerfeed/countdown => the timer's state, then again whenever it changes
+        let timerfeed = warp::path!("timerfeed" / String)
+            .and(output_format())
+            .and(warp::ws())
+            .and(with_db(timers_db))
+            .map(|name: String, format: OutputFormat, ws: warp::ws::Ws, timers: TimersDb| {
+                ws.on_upgrade(move |websocket| timerfeed_handler(name, format, websocket, timers))
+            });
+
+        // GET /time => 200 OK with body "1792243812382 2026-10-17T13:30:12.382Z"
+        let time = warp::path("time")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(output_format())
+            .map(time_handler);
+
+        // WEBSOCKET /timesync "1792243812370" => "1792243812370 1792243812382 1792243812382"
+        let timesync = warp::path("timesync")
+            .and(output_format())
+            .and(warp::ws())
+            .map(|format: OutputFormat, ws: warp::ws::Ws| {
+                ws.on_upgrade(move |websocket| timesync_handler(format, websocket))
+            });
+
+        // GET /leaderboard/arena?limit=10 => 200 OK with body "1 1500 runtime\n2 1200 zkxs..."
+        // GET /leaderboard/arena?around=25&limit=5 => ranks 23 to 27
+        let leaderboard = warp::path!("leaderboard" / String)
+            .and(warp::get())
+            .and(output_format())
+            .and(page())
+            .and(sanitize_policy(text_sanitization))
+            .and(warp::query::<LeaderboardQuery>())
+            .and(with_db(leaderboards_db.clone()))
+            .and_then(leaderboard_handler);
+
+        // DELETE /leaderboard/arena => 200 OK with the number of scores removed
+        let leaderboard_clear = warp::path!("leaderboard" / String)
+            .and(warp::delete())
+            .and(output_format())
+            .and(with_db(leaderboards_db.clone()))
+            .and_then(clear_leaderboard_handler);
+
+        // GET /leaderboard/arena/score?player=runtime => 200 OK with body "3 1500"
+        // POST /leaderboard/arena/score?player=runtime "1500" => 200 OK with body "3 1500 true"
+        // DELETE /leaderboard/arena/score?player=runtime => 200 OK with the removed score "3 1500"
+        let score = warp::path!("leaderboard" / String / "score")
+            .and(warp::get().map(|| ScoreOperation::Get)
+                .or(warp::delete().map(|| ScoreOperation::Remove))
+                .unify()
+                .and(warp::any().map(Bytes::new))
+                .or(warp::post().map(|| ScoreOperation::Submit).and(optional_body(1024 * 16)))
+                .unify())
+            .and(output_format())
+            .and(warp::query::<ScoreQuery>())
+            .and(with_db(leaderboards_db))
+            .and_then(score_handler);
+
+        let rooms_db: RoomsDb = Arc::new(Rooms::new(self.room_max_members, self.room_history, self.room_history_expiry));
+
+        // WEBSOCKET /room/lobby?history=10 => "members 3", then every message sent by other members
+        // GET /room/lobby => 200 OK with body "3/32"
+        let room = warp::path!("room" / String)
+            .and(warp::ws())
+            .and(output_format())
+            .and(warp::query::<RoomQuery>())
+            .and(with_db(rooms_db.clone()))
+            .map(room_handler)
+            .or(warp::path!("room" / String)
+                .and(warp::get())
+                .and(output_format())
+                .and(with_db(rooms_db))
+                .and_then(room_status_handler));
+
+        // GET /systemstat => 200 OK with body containing many system stats
+        let systemstat = warp::path("systemstat")
+            .and(warp::get())
+            .and(output_format())
+            .map(system_stat_handler);
+
+        // GET /sessionlist?client=mentor1 => 200 OK with body containing session list formatted for a specific logix tool
+        // GET /sessionlist/events => the same for the "events" list from the settings file
+        // GET /sessionlist?version=1f0c...&wait=30 => the same, once the list has changed from that version
+        let sessionlist = warp::path("sessionlist")
+            .and(warp::path::end())
+            .map(|| session_list::DEFAULT_LIST_NAME.to_string())
+            .or(warp::path!("sessionlist" / String))
+            .unify()
+            .and(warp::get())
+            .and(output_format())
+            .and(page())
+            .and(sanitize_policy(text_sanitization))
+            .and(uptime_format(self.uptime_format))
+            .and(warp::query::<SessionListQuery>())
+            .and(warp::header::optional::<String>("x-client-id"))
+            .and(with_db(neos_api.clone()))
+            .and(with_db(session_poller_db.clone()))
+            .and(with_db(session_lists_db.clone()))
+            .and(with_db(notification_db))
+            .and(with_db(user_cache_db.clone()))
+            .and_then(sessionlist_handler);
+
+        // WEBSOCKET /sessionfeed?min_users=2 => the session list, then a message each time it changes
+        // WEBSOCKET /sessionfeed/events => the same for the "events" list from the settings file
+        let sessionfeed = warp::path("sessionfeed")
+            .and(warp::path::end())
+            .map(|| session_list::DEFAULT_LIST_NAME.to_string())
+            .or(warp::path!("sessionfeed" / String))
+            .unify()
+            .and(warp::ws())
+            .and(output_format())
+            .and(sanitize_policy(text_sanitization))
+            .and(uptime_format(self.uptime_format))
+            .and(warp::query::<SessionListQuery>())
+            .and(with_db(neos_api.clone()))
+            .and(with_db(session_poller_db.clone()))
+            .and(with_db(session_lists_db.clone()))
+            .and(with_db(user_cache_db.clone()))
+            .map(sessionfeed_handler);
+
+        // GET /sessionevents => the same events as /sessionfeed, as server-sent events
+        let sessionevents = warp::path("sessionevents")
+            .and(warp::path::end())
+            .map(|| session_list::DEFAULT_LIST_NAME.to_string())
+            .or(warp::path!("sessionevents" / String))
+            .unify()
+            .and(warp::get())
+            .and(warp::query::<SessionListQuery>())
+            .and(with_db(neos_api.clone()))
+            .and(with_db(session_poller_db.clone()))
+            .and(with_db(session_lists_db))
+            .and(with_db(user_cache_db.clone()))
+            .map(sessionevents_handler);
+
+        // GET /users => 200 OK with body containing all publicly online users
+        let userlist = warp::path("users")
+            .and(warp::get())
+            .and(output_format())
+            .and(page())
+            .and(sanitize_policy(text_sanitization))
+            .and(with_db(session_poller_db))
+            .and_then(userlist_handler);
+
+        // GET /userRegistration/U-runtime => 200 OK with body "2020-10-13T19:41:20Z"
+        let user_registration = warp::path!("userRegistration" / String)
+            .and(warp::get())
+            .and(output_format())
+            .and(with_db(neos_api))
+            .and(with_db(user_cache_db))
+            .and_then(user_registration_handler);
+
+        // WEBSOCKET /echo
+        let echo = warp::path("echo")
+            .and(warp::ws())
+            .map(|ws: warp::ws::Ws| {
+                // And then our closure will be called when it completes...
+                ws.on_upgrade(|websocket| {
+                    // Just echo all messages back...
+                    let (tx, rx) = websocket.split();
+                    rx.forward(tx).map(|result| {
+                        if let Err(e) = result {
+                            eprintln!("websocket echo error: {:?}", e);
+                        }
+                    })
+                })
+            });
+
+        // WEBSOCKET /wshello
+        let ws_hello = warp::path("wshello")
+            .and(warp::ws())
+            .map(|ws: warp::ws::Ws| {
+                println!("incoming wshello connection");
+                // And then our closure will be called when it completes...
+                ws.on_upgrade(wshello_handler)
+            });
+
+        Ok(hello
+            .or(hello_fallback)
+            .or(init_time)
+            .or(init_time_force)
+            .or(init_time_reset)
+            .or(init_time_peek)
+            .or(systemstat)
+            .or(user_registration)
+            .or(sessionlist)
+            .or(sessionevents)
+            .or(userlist)
+            .or(counter)
+            .or(kv)
+            .or(timer)
+            .or(time)
+            .or(leaderboard)
+            .or(leaderboard_clear)
+            .or(score)
+            .or(sessionfeed)
+            .or(timerfeed)
+            .or(timesync)
+            .or(room)
+            .or(ws_hello)
+            .or(echo)
+            .recover(render_rejection))
+    }
+}
+
+fn with_db<T: Clone + Send>(db: T) -> impl Filter<Extract=(T, ), Error=std::convert::Infallible> + Clone {
+    warp::any().map(move || db.clone())
+}
+
+// a body of at most `limit` bytes, where a request with no body at all counts as an empty one
+fn optional_body(limit: u64) -> impl Filter<Extract=(Bytes, ), Error=warp::Rejection> + Clone {
+    let no_body = warp::header::optional::<String>("content-length")
+        .and(warp::header::optional::<String>("transfer-encoding"))
+        .and_then(|content_length: Option<String>, transfer_encoding: Option<String>| async move {
+            match (content_length, transfer_encoding) {
+                (None, None) => Ok(Bytes::new()),
+                _ => Err(warp::reject()),
+            }
+        });
+    warp::body::content_length_limit(limit)
+        .and(warp::body::bytes())
+        .or(no_body)
+        .unify()
+}
+
+// every route accepts `?format=json|text` and the Accept header; the query parameter wins
+fn output_format() -> impl Filter<Extract=(OutputFormat, ), Error=warp::Rejection> + Clone {
+    warp::query::<FormatQuery>()
+        .and(warp::header::optional::<String>("accept"))
+        .and_then(|query: FormatQuery, accept: Option<String>| async move {
+            OutputFormat::negotiate(query.format.as_deref(), accept.as_deref()).map_err(warp::reject::custom)
+        })
+}
+
+#[derive(Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+// routes that write upstream names into text accept `?sanitize=strip_rich_text,escape_delimiters` (or `none`)
+fn sanitize_policy(default: SanitizePolicy) -> impl Filter<Extract=(SanitizePolicy, ), Error=warp::Rejection> + Clone {
+    warp::query::<SanitizeQuery>()
+        .and_then(move |query: SanitizeQuery| async move {
+            match query.sanitize {
+                Some(names) => SanitizePolicy::from_names(names.split(',').map(str::trim))
+                    .map_err(|e| warp::reject::custom(Error::InvalidQuery(e))),
+                None => Ok(default),
+            }
+        })
+}
+
+#[derive(Deserialize)]
+struct SanitizeQuery {
+    sanitize: Option<String>,
+}
+
+// `?uptime_format=hms|compact|seconds`
+fn uptime_format(default: DurationFormat) -> impl Filter<Extract=(DurationFormat, ), Error=warp::Rejection> + Clone {
+    warp::query::<UptimeFormatQuery>()
+        .and_then(move |query: UptimeFormatQuery| async move {
+            match query.uptime_format {
+                Some(name) => name.parse::<DurationFormat>().map_err(|e| warp::reject::custom(Error::InvalidQuery(e))),
+                None => Ok(default),
+            }
+        })
+}
+
+#[derive(Deserialize)]
+struct UptimeFormatQuery {
+    uptime_format: Option<String>,
+}
+
+// list routes accept `?offset=20&limit=10&footer=true`
+fn page() -> impl Filter<Extract=(Page, ), Error=warp::Rejection> + Clone {
+    warp::query::<PageQuery>()
+        .and_then(|query: PageQuery| async move {
+            Page::parse(query.offset.as_deref(), query.limit.as_deref(), query.footer.as_deref()).map_err(warp::reject::custom)
+        })
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    offset: Option<String>,
+    limit: Option<String>,
+    footer: Option<String>,
+}
+
+// filters reject with an Error when a request is invalid; render those the same way handlers render theirs
+async fn render_rejection(rejection: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    match rejection.find::<Error>() {
+        Some(e) => Ok(e.to_response()),
+        None => Err(rejection),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use chrono::{TimeZone, Utc};
+    use warp::http::StatusCode;
+    use warp::ws::Message;
+
+    use super::*;
+    use crate::client::fake::{self, FakeNeosApi};
+    use crate::json_file::TempFile;
+
+    // every store lives in the test's own temporary files, so tests neither share state nor touch real stores
+    struct TestServer {
+        neos_api: Arc<FakeNeosApi>,
+        files: [TempFile; 3],
+    }
+
+    impl TestServer {
+        fn new(test_name: &str) -> Self {
+            let neos_api = FakeNeosApi::new(
+                vec![
+                    fake::session("S-hub", "Neos Hub", &[("U-zkxs", true), ("U-guest", true), ("U-away", false)]),
+                    fake::session("S-lobby", "Lobby", &[("U-host", true)]),
+                ],
+                vec![fake::user("U-zkxs", Utc.ymd(2020, 10, 13).and_hms(19, 41, 20))],
+            );
+            TestServer {
+                neos_api: Arc::new(neos_api),
+                files: [
+                    TempFile::new(&format!("{}-cache", test_name)),
+                    TempFile::new(&format!("{}-kv", test_name)),
+                    TempFile::new(&format!("{}-leaderboards", test_name)),
+                ],
+            }
+        }
+
+        fn builder(&self) -> ServerBuilder {
+            ServerBuilder::new()
+                .neos_api(self.neos_api.clone())
+                .cache_file(self.files[0].path())
+                .kv_store_file(self.files[1].path())
+                .leaderboard_file(self.files[2].path())
+        }
+
+        fn routes(&self) -> impl Filter<Extract=(impl warp::Reply, ), Error=warp::Rejection> + Clone {
+            self.builder().build().expect("test server should build")
+        }
+    }
+
+    #[tokio::test]
+    async fn user_registration() {
+        let server = TestServer::new("server-user-registration");
+        let routes = server.routes();
+
+        let response = warp::test::request().path("/userRegistration/U-zkxs").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "2020-10-13T19:41:20Z");
+
+        let response = warp::test::request().path("/userRegistration/U-nobody?format=json").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(body["error"], "user_not_found");
+    }
+
+    #[tokio::test]
+    async fn user_list_follows_the_sessions() {
+        let server = TestServer::new("server-user-list");
+        let routes = server.routes();
+
+        let response = warp::test::request().path("/users").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "away\nguest\nhost\nzkxs");
+        assert!(server.neos_api.session_fetches.load(Ordering::SeqCst) >= 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_server_stops_polling() {
+        let server = TestServer::new("server-dropped-poller");
+        let routes = server.builder().session_poll_interval(Duration::from_millis(10)).build().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(server.neos_api.session_fetches.load(Ordering::SeqCst) >= 1);
+
+        drop(routes);
+        // let a tick already in flight finish before counting
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let fetches = server.neos_api.session_fetches.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.neos_api.session_fetches.load(Ordering::SeqCst), fetches);
+    }
+
+    #[tokio::test]
+    async fn session_list_json() {
+        let server = TestServer::new("server-session-list");
+        let routes = server.routes();
+
+        let response = warp::test::request().path("/sessionlist?format=json").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        // only the mentor worlds are on the default list
+        let sessions = body["sessions"].as_array().expect("sessions should be listed");
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0]["session_id"], "S-hub");
+        assert_eq!(sessions[0]["active_users"], 2);
+        assert_eq!(sessions[0]["joined_users"], 3);
+        assert_eq!(sessions[0]["host_present"], true);
+        assert_eq!(sessions[0]["host"]["registration_date"], "2020-10-13T19:41:20Z");
+    }
+
+    #[tokio::test]
+    async fn filtered_polls_keep_their_own_notifications() {
+        let server = TestServer::new("server-filtered-notifications");
+        server.neos_api.sessions.lock().unwrap().push(fake::session("S-quiet", "Neos Hub Quiet", &[("U-host", true)]));
+        let routes = server.routes();
+        let new_sessions = |path: &'static str| {
+            let routes = routes.clone();
+            async move {
+                let response = warp::test::request().path(path).reply(&routes).await;
+                let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+                (body["sessions"].as_array().unwrap().len(), body["new_sessions"].as_bool().unwrap())
+            }
+        };
+
+        assert_eq!(new_sessions("/sessionlist?format=json&client=a").await, (2, true));
+        assert_eq!(new_sessions("/sessionlist?format=json&client=a&min_users=2").await, (1, true));
+        // switching back and forth between the two filters does not make either one's sessions new again
+        assert_eq!(new_sessions("/sessionlist?format=json&client=a").await, (2, false));
+        assert_eq!(new_sessions("/sessionlist?format=json&client=a&min_users=2").await, (1, false));
+        // sorting does not change which sessions are shown
+        assert_eq!(new_sessions("/sessionlist?format=json&client=a&min_users=2&sort=name").await, (1, false));
+    }
+
+    #[tokio::test]
+    async fn rich_text_escapes_partial_tags_in_names() {
+        let server = TestServer::new("server-rich-text-escapes");
+        server.neos_api.sessions.lock().unwrap().push(fake::session("S-odd", "Neos Hub <color=red", &[("U-zkxs", true)]));
+        let response = warp::test::request().path("/sessionlist?rich_text=true").reply(&server.routes()).await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("(Neos Hub <noparse><</noparse>color=red)"), "unexpected body {:?}", body);
+
+        // without rich text there is no markup to break, so the name is left alone
+        let response = warp::test::request().path("/sessionlist").reply(&server.routes()).await;
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.contains("(Neos Hub <color=red)"), "unexpected body {:?}", body);
+    }
+
+    #[tokio::test]
+    async fn session_list_version_follows_the_footer() {
+        let server = TestServer::new("server-session-list-version");
+        let response = warp::test::request().path("/sessionlist?footer=true").reply(&server.routes()).await;
+        let version = response.headers()["x-list-version"].to_str().unwrap().to_string();
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        let lines = body.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[lines.len() - 2..], ["total=1 more=false".to_string(), format!("version={}", version)]);
+    }
+
+    #[tokio::test]
+    async fn long_poll_wakes_up_when_the_list_changes() {
+        let server = TestServer::new("server-long-poll");
+        let routes = server.builder().session_poll_interval(Duration::from_millis(20)).build().unwrap();
+        let response = warp::test::request().path("/sessionlist?format=json").reply(&routes).await;
+        let version = response.headers()["x-list-version"].to_str().unwrap().to_string();
+
+        let mut long_poll = {
+            let routes = routes.clone();
+            let path = format!("/sessionlist?format=json&version={}&wait=10", version);
+            tokio::spawn(async move { warp::test::request().path(&path).reply(&routes).await })
+        };
+        assert!(tokio::time::timeout(Duration::from_millis(100), &mut long_poll).await.is_err(), "the long poll should wait while the list is unchanged");
+
+        server.neos_api.sessions.lock().unwrap().push(fake::session("S-new", "Neos Hub 2", &[("U-zkxs", true)]));
+        let response = tokio::time::timeout(Duration::from_secs(5), long_poll).await
+            .expect("the long poll should answer once the list changes")
+            .unwrap();
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_ne!(body["version"], version.as_str());
+        assert_eq!(body["sessions"].as_array().unwrap().len(), 2);
+    }
+
+    // read the stream until the next event with this name, keeping whatever arrived after it for the next call
+    async fn next_event(body: &mut hyper::Body, received: &mut String, name: &str) -> serde_json::Value {
+        use hyper::body::HttpBody;
+
+        let pattern = format!("event:{}\ndata:", name);
+        loop {
+            if let Some(start) = received.find(&pattern) {
+                let data_start = start + pattern.len();
+                if let Some(length) = received[data_start..].find('\n') {
+                    let event = serde_json::from_str(&received[data_start..data_start + length]).unwrap();
+                    received.drain(..data_start + length);
+                    return event;
+                }
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.data()).await
+                .expect("events should keep arriving")
+                .expect("the stream should not end")
+                .unwrap();
+            received.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn session_events_stream() {
+        use warp::Reply;
+
+        let server = TestServer::new("server-session-events");
+        let routes = server.builder().session_poll_interval(Duration::from_millis(20)).build().unwrap();
+        // the stream never ends, so read it as it arrives instead of waiting for the whole body
+        let response = warp::test::request().path("/sessionevents").filter(&routes).await.unwrap().into_response();
+        assert_eq!(response.headers()[warp::http::header::CONTENT_TYPE], "text/event-stream");
+        let mut body = response.into_body();
+        let mut received = String::new();
+
+        let snapshot = next_event(&mut body, &mut received, "snapshot").await;
+        assert_eq!(snapshot["sessions"][0]["session_id"], "S-hub");
+        server.neos_api.sessions.lock().unwrap().push(fake::session("S-new", "Neos Hub 2", &[("U-zkxs", true)]));
+        let added = next_event(&mut body, &mut received, "session_added").await;
+        assert_eq!(added["session"]["session_id"], "S-new");
+    }
+
+    #[tokio::test]
+    async fn init_time_rejects_non_integers() {
+        let server = TestServer::new("server-init-time");
+        let routes = server.routes();
+
+        let response = warp::test::request().method("POST").path("/initTime").body("abc").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        assert_eq!(body["error"], "invalid_value");
+
+        let response = warp::test::request().method("POST").path("/initTime").body("100").reply(&routes).await;
+        assert_eq!(response.body(), "100");
+        let response = warp::test::request().method("POST").path("/initTime").body("200").reply(&routes).await;
+        assert_eq!(response.body(), "100");
+    }
+
+    #[tokio::test]
+    async fn server_time() {
+        let server = TestServer::new("server-time");
+        let routes = server.routes();
+        let before = Utc::now().timestamp_millis();
+
+        let response = warp::test::request().path("/time").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        let (unix_ms, iso_8601) = body.split_once(' ').expect("the time should be given twice");
+        let unix_ms = unix_ms.parse::<i64>().unwrap();
+        assert!(unix_ms >= before && unix_ms <= Utc::now().timestamp_millis());
+        // both halves are the same instant, to the millisecond
+        assert!(iso_8601.ends_with('Z'), "unexpected time {:?}", iso_8601);
+        assert_eq!(chrono::DateTime::parse_from_rfc3339(iso_8601).unwrap().timestamp_millis(), unix_ms);
+
+        let response = warp::test::request().path("/time?format=json").reply(&routes).await;
+        let body = serde_json::from_slice::<serde_json::Value>(response.body()).unwrap();
+        let unix_ms = body["unix_ms"].as_i64().expect("unix_ms should be a number");
+        let iso_8601 = body["iso_8601"].as_str().expect("iso_8601 should be a string");
+        assert_eq!(chrono::DateTime::parse_from_rfc3339(iso_8601).unwrap().timestamp_millis(), unix_ms);
+    }
+
+    #[tokio::test]
+    async fn time_sync_echoes_the_client_time() {
+        let server = TestServer::new("server-time-sync");
+        let mut client = warp::test::ws().path("/timesync").handshake(server.routes()).await
+            .expect("/timesync should accept WebSockets");
+        let before = Utc::now().timestamp_millis();
+        client.send_text(" 1792243812370\n").await;
+        let reply = client.recv().await.unwrap();
+        let times = reply.to_str().unwrap().split(' ').collect::<Vec<&str>>();
+        assert_eq!(times.len(), 3, "unexpected reply {:?}", reply);
+        assert_eq!(times[0], "1792243812370");
+        let (received, sent) = (times[1].parse::<i64>().unwrap(), times[2].parse::<i64>().unwrap());
+        assert!(before <= received && received <= sent && sent <= Utc::now().timestamp_millis());
+
+        let mut client = warp::test::ws().path("/timesync?format=json").handshake(server.routes()).await
+            .expect("/timesync should accept WebSockets");
+        client.send_text("1792243812370").await;
+        let reply = serde_json::from_str::<serde_json::Value>(client.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(reply["client_time"], 1792243812370_i64);
+        assert!(reply["server_receive_ms"].as_i64().unwrap() <= reply["server_send_ms"].as_i64().unwrap());
+        // anything that is not JSON comes back as a string
+        client.send_text("t0").await;
+        let reply = serde_json::from_str::<serde_json::Value>(client.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(reply["client_time"], "t0");
+    }
+
+    #[tokio::test]
+    async fn init_time_and_counter_survive_restarts() {
+        let server = TestServer::new("server-legacy-routes");
+        let routes = server.routes();
+        let post = |path: &'static str, body: &'static str| warp::test::request().method("POST").path(path).body(body);
+        assert_eq!(warp::test::request().path("/initTimePeek").reply(&routes).await.body(), "None");
+        assert_eq!(post("/initTime", "100").reply(&routes).await.body(), "100");
+        assert_eq!(warp::test::request().path("/counter").reply(&routes).await.body(), "Some(0)");
+        assert_eq!(warp::test::request().path("/counter").reply(&routes).await.body(), "Some(1)");
+
+        // the same values, from the store file, after a restart
+        let routes = server.routes();
+        assert_eq!(post("/initTime", "200").reply(&routes).await.body(), "100");
+        assert_eq!(warp::test::request().path("/initTimePeek").reply(&routes).await.body(), "Some(100)");
+        assert_eq!(warp::test::request().path("/counter").reply(&routes).await.body(), "Some(2)");
+        // and through /kv
+        assert_eq!(warp::test::request().path("/kv/legacy/initTime").reply(&routes).await.body(), "100");
+
+        assert_eq!(post("/initTimeForce", "300").reply(&routes).await.body(), "300");
+        assert_eq!(post("/initTimeReset", "").reply(&routes).await.body(), "");
+        assert_eq!(post("/initTimeReset", "").reply(&routes).await.status(), StatusCode::OK);
+        assert_eq!(warp::test::request().path("/initTimePeek").reply(&routes).await.body(), "None");
+
+        post("/kv/legacy/initTime", "soon").reply(&routes).await;
+        let response = warp::test::request().path("/initTimePeek").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn system_stat_uptime() {
+        let server = TestServer::new("server-system-stat");
+        let response = warp::test::request().path("/systemstat").reply(&server.routes()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        let uptime = body.lines()
+            .find_map(|line| line.strip_prefix("Uptime: "))
+            .expect("system stats should include the uptime");
+        // compact, e.g. 5h12m, unless the platform cannot tell
+        assert!(uptime.starts_with("error") || uptime.ends_with(['d', 'h', 'm', 's']), "unexpected uptime \"{}\"", uptime);
+    }
+
+    #[tokio::test]
+    async fn session_feed_survives_pings_during_the_first_snapshot() {
+        let server = TestServer::new("server-session-feed-pings");
+        // every upstream call takes several times longer than the gap between pings
+        *server.neos_api.latency.lock().unwrap() = Duration::from_millis(100);
+        let mut client = warp::test::ws().path("/sessionfeed?format=json").handshake(server.routes()).await
+            .expect("the session feed should accept WebSockets");
+
+        let snapshot = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                client.send(Message::ping(Vec::new())).await;
+                match tokio::time::timeout(Duration::from_millis(20), client.recv()).await {
+                    Ok(Ok(message)) if message.is_text() => return message,
+                    Ok(Ok(_)) | Err(_) => {}
+                    Ok(Err(e)) => panic!("the session feed failed: {}", e),
+                }
+            }
+        }).await.expect("the first snapshot should arrive while the client is sending pings");
+        let snapshot = serde_json::from_str::<serde_json::Value>(snapshot.to_str().unwrap()).unwrap();
+        assert_eq!(snapshot["event"], "snapshot");
+        assert_eq!(snapshot["sessions"][0]["session_id"], "S-hub");
+    }
+
+    #[tokio::test]
+    async fn session_feed_marks_added_sessions_new() {
+        let server = TestServer::new("server-session-feed-new");
+        let routes = server.builder().session_poll_interval(Duration::from_millis(20)).build().unwrap();
+        let mut client = warp::test::ws().path("/sessionfeed?rich_text=true").handshake(routes).await
+            .expect("the session feed should accept WebSockets");
+        let snapshot = client.recv().await.unwrap();
+        // sessions already listed on connect are not new
+        assert!(!snapshot.to_str().unwrap().contains("<b>"), "unexpected snapshot {:?}", snapshot);
+
+        server.neos_api.sessions.lock().unwrap().push(fake::session("S-new", "Neos Hub 2", &[("U-zkxs", true)]));
+        let added = tokio::time::timeout(Duration::from_secs(5), client.recv()).await
+            .expect("the new session should be announced")
+            .unwrap();
+        let added = added.to_str().unwrap();
+        assert!(added.starts_with("session_added S-new <b>zkxs (Neos Hub 2)"), "unexpected event {:?}", added);
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+
+use crate::dto::response_dto::{SessionEvent, SessionListEntry};
+use crate::session_list;
+use crate::session_poller::SessionSnapshot;
+
+/// how long a `/sessionlist` long poll waits for a change unless the client says otherwise
+pub const DEFAULT_LONG_POLL_WAIT: Duration = Duration::from_secs(30);
+/// the longest a client may ask a long poll to wait
+pub const MAX_LONG_POLL_WAIT: Duration = Duration::from_secs(120);
+
+/// A short token identifying a version of a session list. It covers exactly what [`diff`] compares, so it changes if
+/// and only if there would be events between the two versions.
+pub fn version(entries: &[SessionListEntry]) -> String {
+    let mut hasher = DefaultHasher::new();
+    for entry in entries {
+        entry.session_id.hash(&mut hasher);
+        entry.active_users.hash(&mut hasher);
+        entry.joined_users.hash(&mut hasher);
+        entry.host_present.hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+/// The events that turn one version of a session list into the next, in the order of the new list with removals
+/// last. `snapshot` is the snapshot `current` was built from, which tells us why a session left the list.
+pub fn diff(previous: &[SessionListEntry], current: &[SessionListEntry], snapshot: &SessionSnapshot) -> Vec<SessionEvent> {
+    let previous_by_id = previous.iter()
+        .map(|entry| (entry.session_id.as_str(), entry))
+        .collect::<HashMap<&str, &SessionListEntry>>();
+    let current_ids = current.iter()
+        .map(|entry| entry.session_id.as_str())
+        .collect::<HashSet<&str>>();
+
+    let mut events = Vec::new();
+    for entry in current {
+        match previous_by_id.get(entry.session_id.as_str()) {
+            None => events.push(SessionEvent::SessionAdded {
+                session: SessionListEntry { is_new: true, ..entry.clone() },
+            }),
+            Some(old) => {
+                if old.host_present && !entry.host_present {
+                    events.push(SessionEvent::HostLeft { session_id: entry.session_id.clone() });
+                }
+                if old.active_users != entry.active_users || old.joined_users != entry.joined_users {
+                    events.push(SessionEvent::UserCountChanged {
+                        session_id: entry.session_id.clone(),
+                        active_users: entry.active_users,
+                        joined_users: entry.joined_users,
+                    });
+                }
+            }
+        }
+    }
+
+    for old in previous.iter().filter(|old| !current_ids.contains(old.session_id.as_str())) {
+        // lists that require a present host drop a session as its host leaves, so say why it went
+        let host_left = old.host_present && snapshot.sessions.iter()
+            .any(|session| session.session_id == old.session_id && !session_list::host_present(session));
+        if host_left {
+            events.push(SessionEvent::HostLeft { session_id: old.session_id.clone() });
+        }
+        events.push(SessionEvent::SessionRemoved { session_id: old.session_id.clone() });
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use chrono::{TimeZone, Utc};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::client::fake;
+
+    fn entry(session_id: &str, active_users: i32, joined_users: i32, host_present: bool) -> SessionListEntry {
+        SessionListEntry {
+            session_id: session_id.to_string(),
+            name: format!("{} world", session_id),
+            host_username: "host".to_string(),
+            host_user_id: Some("U-host".to_string()),
+            active_users,
+            joined_users,
+            session_begin_time: Utc.ymd(2022, 1, 1).and_hms(0, 0, 0),
+            uptime_secs: 3600,
+            host_present,
+            away_since: None,
+            is_new: false,
+            host: None,
+            host_error: None,
+            new_users: None,
+        }
+    }
+
+    fn snapshot(sessions: Vec<Value>) -> SessionSnapshot {
+        SessionSnapshot {
+            sessions: serde_json::from_value(Value::Array(sessions)).expect("fake sessions should parse"),
+            fetched_at: Instant::now(),
+        }
+    }
+
+    // events in their JSON form, which is what clients see
+    fn events(previous: &[SessionListEntry], current: &[SessionListEntry], snapshot: &SessionSnapshot) -> Vec<Value> {
+        diff(previous, current, snapshot).iter()
+            .map(|event| serde_json::to_value(event).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_list_has_no_events() {
+        let list = [entry("S-a", 2, 3, true), entry("S-b", 1, 1, true)];
+        assert!(events(&list, &list, &snapshot(vec![])).is_empty());
+    }
+
+    #[test]
+    fn added_sessions_are_new() {
+        let events = events(&[], &[entry("S-a", 2, 3, true)], &snapshot(vec![]));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "session_added");
+        assert_eq!(events[0]["session"]["session_id"], "S-a");
+        assert_eq!(events[0]["session"]["is_new"], true);
+    }
+
+    #[test]
+    fn user_counts_and_hosts_leaving() {
+        let previous = [entry("S-a", 2, 3, true), entry("S-b", 1, 1, true)];
+        let current = [entry("S-a", 3, 4, true), entry("S-b", 1, 1, false)];
+        assert_eq!(events(&previous, &current, &snapshot(vec![])), vec![
+            json!({"event": "user_count_changed", "session_id": "S-a", "active_users": 3, "joined_users": 4}),
+            json!({"event": "host_left", "session_id": "S-b"}),
+        ]);
+    }
+
+    #[test]
+    fn removals_come_last_and_say_why() {
+        let previous = [entry("S-gone", 1, 1, true), entry("S-hostless", 2, 2, true), entry("S-a", 1, 1, true)];
+        let current = [entry("S-a", 1, 1, true), entry("S-new", 1, 1, true)];
+        // S-hostless is still listed upstream, but its host has left, while S-gone ended altogether
+        let snapshot = snapshot(vec![
+            fake::session("S-a", "a", &[("U-host", true)]),
+            fake::session("S-hostless", "hostless", &[("U-host", false), ("U-guest", true)]),
+        ]);
+        let events = events(&previous, &current, &snapshot);
+        let names = events.iter()
+            .map(|event| format!("{} {}", event["event"].as_str().unwrap(), event["session_id"].as_str().unwrap_or_else(|| event["session"]["session_id"].as_str().unwrap())))
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec![
+            "session_added S-new",
+            "session_removed S-gone",
+            "host_left S-hostless",
+            "session_removed S-hostless",
+        ]);
+    }
+
+    #[test]
+    fn version_covers_what_diff_compares() {
+        let list = vec![entry("S-a", 2, 3, true), entry("S-b", 1, 1, true)];
+        let base = version(&list);
+        assert_eq!(base.len(), 16);
+
+        let mut renamed = list.clone();
+        renamed[0].name = "renamed".to_string();
+        renamed[0].uptime_secs += 60;
+        assert_eq!(version(&renamed), base);
+
+        let mut changed = list.clone();
+        changed[1].active_users = 0;
+        assert_ne!(version(&changed), base);
+        let mut host_left = list.clone();
+        host_left[0].host_present = false;
+        assert_ne!(version(&host_left), base);
+        assert_ne!(version(&list[1..]), base);
+        assert_ne!(version(&[list[1].clone(), list[0].clone()]), base);
+    }
+}
@@ -0,0 +1,571 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::dto::response_dto::{NewUser, SessionListEntry};
+use crate::dto::session_dto::Session;
+use crate::dto::user_dto::AbridgedUser;
+use crate::error::Error;
+use crate::rich_text::{ColorRuleConfig, ColorRules};
+use crate::template::{self, Template};
+
+/// name of the list served at `/sessionlist`
+pub const DEFAULT_LIST_NAME: &str = "default";
+/// name of the built-in line template, [`template::DEFAULT_TEMPLATE`]
+pub const DEFAULT_TEMPLATE_NAME: &str = "default";
+
+// world IDs change on republish, so we'll just stick with name checking for now
+const WORLD_NAME_PREFIXES: [&str; 5] = [
+    "MTC",
+    "Metaverse Training",
+    "Neos Hub",
+    "The Avatar Station",
+    "Training"
+];
+
+/// Which sessions belong in a session list.
+///
+/// A session is a candidate if its name starts with any of the prefixes, matches any of the patterns, or carries any of
+/// the tags. A list with no prefixes, patterns or tags considers every session a candidate. Candidates must then pass
+/// every enabled predicate.
+#[derive(Clone)]
+pub struct SessionListDefinition {
+    pub world_name_prefixes: Vec<String>,
+    pub world_name_patterns: Vec<Regex>,
+    pub tags: Vec<String>,
+    /// only sessions the API marks as valid
+    pub require_valid: bool,
+    /// skip sessions that have ended
+    pub exclude_ended: bool,
+    /// skip sessions where nobody is active
+    pub require_active_users: bool,
+    /// skip sessions whose host has left
+    pub require_host_present: bool,
+    /// If set, only list sessions whose host or any present registered user signed up at most this many days ago.
+    /// This needs every present user's account, not just the host's.
+    pub new_user_max_age_days: Option<i64>,
+    /// name of the template text responses use unless a request picks another
+    pub template: String,
+    /// style text responses with the color rules unless a request asks otherwise
+    pub rich_text: bool,
+}
+
+/// Every configured session list and line template, by name, and the rich-text color rules they share. Always
+/// contains [`DEFAULT_LIST_NAME`] and [`DEFAULT_TEMPLATE_NAME`].
+#[derive(Clone)]
+pub struct SessionLists {
+    lists: BTreeMap<String, SessionListDefinition>,
+    templates: BTreeMap<String, Arc<Template>>,
+    pub color_rules: ColorRules,
+}
+
+/// A session list as written in the settings file. Predicates default to on.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionListConfig {
+    #[serde(default)]
+    world_name_prefixes: Vec<String>,
+    #[serde(default)]
+    world_name_patterns: Vec<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default = "enabled")]
+    require_valid: bool,
+    #[serde(default = "enabled")]
+    exclude_ended: bool,
+    #[serde(default = "enabled")]
+    require_active_users: bool,
+    #[serde(default = "enabled")]
+    require_host_present: bool,
+    new_user_max_age_days: Option<i64>,
+    template: Option<String>,
+    #[serde(default)]
+    rich_text: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl SessionListDefinition {
+    /// the mentor list that `/sessionlist` has always served
+    pub fn new_user_worlds() -> Self {
+        SessionListDefinition {
+            world_name_prefixes: WORLD_NAME_PREFIXES.iter().map(|prefix| prefix.to_string()).collect(),
+            world_name_patterns: Vec::new(),
+            tags: Vec::new(),
+            require_valid: true,
+            exclude_ended: true,
+            require_active_users: true,
+            require_host_present: true,
+            new_user_max_age_days: None,
+            template: DEFAULT_TEMPLATE_NAME.to_string(),
+            rich_text: false,
+        }
+    }
+
+    pub fn matches(&self, session: &Session) -> bool {
+        self.is_candidate(session)
+            && (!self.require_valid || session.is_valid)
+            && (!self.exclude_ended || !session.has_ended)
+            && (!self.require_active_users || session.active_users > 0)
+            && (!self.require_host_present || host_present(session))
+    }
+
+    /// IDs of every user whose account must be looked up to build an entry for this session
+    pub fn users_to_look_up(&self, session: &Session) -> Vec<String> {
+        let mut user_ids = session.host_user_id.iter().cloned().collect::<Vec<String>>();
+        if self.new_user_max_age_days.is_some() {
+            user_ids.extend(session.session_users.iter()
+                .filter(|user| user.is_present)
+                .filter_map(|user| user.user_id.clone()));
+        }
+        user_ids
+    }
+
+    /// In new-user mode, the host and present users whose accounts are recent enough, youngest first. `None` when not
+    /// in new-user mode; an empty list means the session does not belong in this list.
+    pub fn new_users(&self, session: &Session, users: &HashMap<String, Result<AbridgedUser, Error>>, now: DateTime<Utc>) -> Option<Vec<NewUser>> {
+        let max_age_days = self.new_user_max_age_days?;
+        let mut new_users = Vec::new();
+        let host = session.host_user_id.as_ref().map(|user_id| (session.host_username.as_str(), user_id));
+        let present_users = session.session_users.iter()
+            .filter(|user| user.is_present && user.user_id != session.host_user_id)
+            .filter_map(|user| user.user_id.as_ref().map(|user_id| (user.username.as_str(), user_id)));
+        for (is_host, (username, user_id)) in host.into_iter().map(|user| (true, user)).chain(present_users.map(|user| (false, user))) {
+            if let Some(Ok(user)) = users.get(user_id) {
+                let account_age_days = now.signed_duration_since(user.registration_date).num_days();
+                if account_age_days <= max_age_days {
+                    new_users.push(NewUser {
+                        username: username.to_string(),
+                        user_id: user_id.clone(),
+                        is_host,
+                        account_age_days,
+                    });
+                }
+            }
+        }
+        new_users.sort_by_key(|user| user.account_age_days);
+        Some(new_users)
+    }
+
+    fn is_candidate(&self, session: &Session) -> bool {
+        if self.world_name_prefixes.is_empty() && self.world_name_patterns.is_empty() && self.tags.is_empty() {
+            return true;
+        }
+        self.world_name_prefixes.iter().any(|prefix| session.name.starts_with(prefix.as_str()))
+            || self.world_name_patterns.iter().any(|pattern| pattern.is_match(&session.name))
+            || self.tags.iter().any(|tag| session.tags.contains(tag))
+    }
+}
+
+impl SessionLists {
+    /// Validate configured lists, templates and color rules. A configured `default` list or template replaces the
+    /// built-in one, and configured color rules replace the built-in ones.
+    pub fn from_config(config: BTreeMap<String, SessionListConfig>, templates: BTreeMap<String, String>, color_rules: Option<Vec<ColorRuleConfig>>) -> Result<Self, String> {
+        let mut lists = SessionLists::default();
+        if let Some(color_rules) = color_rules {
+            lists.color_rules = ColorRules::from_config(color_rules)?;
+        }
+        for (name, template) in templates {
+            let template = template.parse::<Template>()
+                .map_err(|e| format!("invalid template \"{}\": {}", name, e))?;
+            lists.templates.insert(name, Arc::new(template));
+        }
+        for (name, list) in config {
+            if name.is_empty() || name.contains('/') {
+                return Err(format!("invalid session list name \"{}\"", name));
+            }
+            if list.new_user_max_age_days.is_some_and(|days| days < 0) {
+                return Err(format!("new_user_max_age_days in session list \"{}\" must not be negative", name));
+            }
+            let mut world_name_patterns = Vec::with_capacity(list.world_name_patterns.len());
+            for pattern in list.world_name_patterns.iter() {
+                let regex = Regex::new(pattern)
+                    .map_err(|e| format!("invalid world name pattern in session list \"{}\": {}", name, e))?;
+                world_name_patterns.push(regex);
+            }
+            let template = list.template.unwrap_or_else(|| DEFAULT_TEMPLATE_NAME.to_string());
+            if !lists.templates.contains_key(&template) {
+                return Err(format!("session list \"{}\" uses unknown template \"{}\"", name, template));
+            }
+            lists.lists.insert(name, SessionListDefinition {
+                world_name_prefixes: list.world_name_prefixes,
+                world_name_patterns,
+                tags: list.tags,
+                require_valid: list.require_valid,
+                exclude_ended: list.exclude_ended,
+                require_active_users: list.require_active_users,
+                require_host_present: list.require_host_present,
+                new_user_max_age_days: list.new_user_max_age_days,
+                template,
+                rich_text: list.rich_text,
+            });
+        }
+        Ok(lists)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SessionListDefinition> {
+        self.lists.get(name)
+    }
+
+    pub fn template(&self, name: &str) -> Option<Arc<Template>> {
+        self.templates.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.lists.keys().map(|name| name.as_str())
+    }
+
+    pub fn template_names(&self) -> impl Iterator<Item=&str> {
+        self.templates.keys().map(|name| name.as_str())
+    }
+}
+
+impl Default for SessionLists {
+    fn default() -> Self {
+        let mut lists = BTreeMap::new();
+        lists.insert(DEFAULT_LIST_NAME.to_string(), SessionListDefinition::new_user_worlds());
+        let mut templates = BTreeMap::new();
+        let default_template = template::DEFAULT_TEMPLATE.parse::<Template>().expect("built-in template should parse");
+        templates.insert(DEFAULT_TEMPLATE_NAME.to_string(), Arc::new(default_template));
+        SessionLists {
+            lists,
+            templates,
+            color_rules: ColorRules::default(),
+        }
+    }
+}
+
+/// Per-request refinements of a session list, from `/sessionlist` query parameters. Unset fields do not filter.
+#[derive(Default, Debug)]
+pub struct SessionFilter {
+    pub min_active_users: Option<i32>,
+    pub max_active_users: Option<i32>,
+    pub min_host_age_days: Option<i64>,
+    pub max_host_age_days: Option<i64>,
+    pub patron: Option<bool>,
+    pub headless: Option<bool>,
+    pub mobile_friendly: Option<bool>,
+    /// the session's access level must be one of these
+    pub access_levels: Vec<String>,
+    /// the session must carry every one of these
+    pub tags: Vec<String>,
+    pub neos_version: Option<String>,
+}
+
+impl SessionFilter {
+    /// Identifies the sessions this filter selects: filters that differ only in the order or case of access levels, or
+    /// in the order of tags, have the same key.
+    pub fn key(&self) -> String {
+        let mut access_levels = self.access_levels.iter().map(|level| level.to_ascii_lowercase()).collect::<Vec<_>>();
+        access_levels.sort();
+        access_levels.dedup();
+        let mut tags = self.tags.clone();
+        tags.sort();
+        tags.dedup();
+        let normalized = SessionFilter {
+            access_levels,
+            tags,
+            neos_version: self.neos_version.clone(),
+            ..*self
+        };
+        format!("{:?}", normalized)
+    }
+
+    /// Everything that can be checked without looking up the host
+    pub fn matches_session(&self, session: &Session) -> bool {
+        self.min_active_users.is_none_or(|min| session.active_users >= min)
+            && self.max_active_users.is_none_or(|max| session.active_users <= max)
+            && self.headless.is_none_or(|headless| session.headless_host == headless)
+            && self.mobile_friendly.is_none_or(|mobile_friendly| session.mobile_friendly == mobile_friendly)
+            && (self.access_levels.is_empty() || self.access_levels.iter().any(|level| level.eq_ignore_ascii_case(&session.access_level)))
+            && self.tags.iter().all(|tag| session.tags.contains(tag))
+            && self.neos_version.as_ref().is_none_or(|version| &session.neos_version == version)
+    }
+
+    /// Check the host-based filters. Sessions whose host is unknown only pass if no host filter is set.
+    pub fn matches_host(&self, host: Option<&AbridgedUser>, now: DateTime<Utc>) -> bool {
+        if self.min_host_age_days.is_none() && self.max_host_age_days.is_none() && self.patron.is_none() {
+            return true;
+        }
+        match host {
+            Some(host) => {
+                let age_days = now.signed_duration_since(host.registration_date).num_days();
+                self.min_host_age_days.is_none_or(|min| age_days >= min)
+                    && self.max_host_age_days.is_none_or(|max| age_days <= max)
+                    && self.patron.is_none_or(|patron| host.is_patron == patron)
+            }
+            None => false,
+        }
+    }
+}
+
+/// What to sort a session list by
+#[derive(Clone, Copy)]
+pub enum SortKey {
+    Uptime,
+    ActiveUsers,
+    HostRegistration,
+    Name,
+}
+
+/// How to order a session list. By default this is newest sessions first.
+pub struct SessionSort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl SortKey {
+    pub fn parse(key: &str) -> Option<Self> {
+        match key {
+            "uptime" => Some(SortKey::Uptime),
+            "users" => Some(SortKey::ActiveUsers),
+            "host_registration" => Some(SortKey::HostRegistration),
+            "name" => Some(SortKey::Name),
+            _ => None,
+        }
+    }
+
+    /// the order that is most useful for each key: newest sessions, busiest sessions, newest accounts, A to Z
+    pub fn default_descending(self) -> bool {
+        match self {
+            SortKey::Uptime => false,
+            SortKey::ActiveUsers => true,
+            SortKey::HostRegistration => true,
+            SortKey::Name => false,
+        }
+    }
+}
+
+impl Default for SessionSort {
+    fn default() -> Self {
+        SessionSort {
+            key: SortKey::Uptime,
+            descending: SortKey::Uptime.default_descending(),
+        }
+    }
+}
+
+impl SessionSort {
+    /// Sort entries in place. Ties, and sessions whose host is unknown when sorting by host registration, keep
+    /// newest-first order; unknown hosts always go last.
+    pub fn sort(&self, entries: &mut [SessionListEntry]) {
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.session_begin_time));
+        entries.sort_by(|a, b| {
+            let ordering = match self.key {
+                SortKey::Uptime => a.uptime_secs.cmp(&b.uptime_secs),
+                SortKey::ActiveUsers => a.active_users.cmp(&b.active_users),
+                SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+                SortKey::HostRegistration => {
+                    let a = a.host.as_ref().map(|host| host.registration_date);
+                    let b = b.host.as_ref().map(|host| host.registration_date);
+                    return match (a, b) {
+                        (Some(a), Some(b)) if self.descending => b.cmp(&a),
+                        (Some(a), Some(b)) => a.cmp(&b),
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (None, None) => Ordering::Equal,
+                    };
+                }
+            };
+            if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+}
+
+/// whether the host is still in their own session
+pub fn host_present(session: &Session) -> bool {
+    let users = &session.session_users;
+    if session.host_user_id.is_some() {
+        users.iter().any(|u| u.is_present && u.user_id == session.host_user_id)
+    } else {
+        users.iter().any(|u| u.is_present && u.username == session.host_username)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::client::fake;
+    use crate::dto::response_dto::SessionHost;
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2022, 6, 1).and_hms(12, 0, 0)
+    }
+
+    // a session with its upstream JSON adjusted first
+    fn session(name: &str, users: &[(&str, bool)], adjust: impl FnOnce(&mut Value)) -> Session {
+        let mut session = fake::session("S-a", name, users);
+        adjust(&mut session);
+        serde_json::from_value(session).expect("fake session should parse")
+    }
+
+    fn host(age_days: i64, is_patron: bool) -> AbridgedUser {
+        AbridgedUser {
+            registration_date: now() - Duration::days(age_days),
+            is_patron,
+            cache_time: now(),
+        }
+    }
+
+    fn parse_list(config: &str) -> Result<SessionListDefinition, String> {
+        let config = toml::from_str::<BTreeMap<String, SessionListConfig>>(config).unwrap();
+        SessionLists::from_config(config, BTreeMap::new(), None).map(|lists| lists.get("test").unwrap().clone())
+    }
+
+    #[test]
+    fn candidates_by_prefix_pattern_or_tag() {
+        let list = parse_list("[test]\nworld_name_prefixes = [\"MTC\"]\nworld_name_patterns = [\"(?i)mentor\"]\ntags = [\"help\"]").unwrap();
+        let present = [("U-host", true)];
+        assert!(list.matches(&session("MTC Lobby", &present, |_| {})));
+        assert!(!list.matches(&session("The MTC", &present, |_| {})));
+        assert!(list.matches(&session("Ask a MENTOR", &present, |_| {})));
+        assert!(list.matches(&session("Somewhere", &present, |session| session["tags"] = serde_json::json!(["help"]))));
+        assert!(!list.matches(&session("Somewhere", &present, |session| session["tags"] = serde_json::json!(["helpful"]))));
+
+        // a list without any of them takes every session
+        let everything = parse_list("[test]").unwrap();
+        assert!(everything.matches(&session("Somewhere", &present, |_| {})));
+    }
+
+    #[test]
+    fn list_predicates() {
+        let list = parse_list("[test]").unwrap();
+        assert!(list.matches(&session("a", &[("U-host", true)], |_| {})));
+        assert!(!list.matches(&session("a", &[("U-host", true)], |session| session["isValid"] = false.into())));
+        assert!(!list.matches(&session("a", &[("U-host", true)], |session| session["hasEnded"] = true.into())));
+        assert!(!list.matches(&session("a", &[("U-host", false), ("U-guest", true)], |_| {})));
+        assert!(!list.matches(&session("a", &[("U-host", true)], |session| session["activeUsers"] = 0.into())));
+
+        let lenient = parse_list("[test]\nrequire_valid = false\nexclude_ended = false\nrequire_active_users = false\nrequire_host_present = false").unwrap();
+        assert!(lenient.matches(&session("a", &[("U-host", false)], |session| {
+            session["isValid"] = false.into();
+            session["hasEnded"] = true.into();
+            session["activeUsers"] = 0.into();
+        })));
+    }
+
+    #[test]
+    fn invalid_lists() {
+        assert!(parse_list("[test]\nworld_name_patterns = [\"(\"]").err().unwrap().starts_with("invalid world name pattern in session list \"test\""));
+        assert_eq!(parse_list("[test]\ntemplate = \"fancy\"").err().unwrap(), "session list \"test\" uses unknown template \"fancy\"");
+        assert_eq!(parse_list("[\"a/b\"]").err().unwrap(), "invalid session list name \"a/b\"");
+    }
+
+    #[test]
+    fn hosts_without_user_ids_are_found_by_name() {
+        let unregistered = session("a", &[("U-host", true)], |session| {
+            session["hostUserId"] = Value::Null;
+            session["sessionUsers"][0]["userID"] = Value::Null;
+        });
+        assert!(host_present(&unregistered));
+        let impostor = session("a", &[("U-host", false), ("U-other", true)], |session| {
+            session["sessionUsers"][1]["username"] = "host".into();
+        });
+        assert!(!host_present(&impostor));
+    }
+
+    #[test]
+    fn session_filters() {
+        let busy = session("a", &[("U-host", true), ("U-a", true), ("U-b", true)], |session| {
+            session["accessLevel"] = "Friends".into();
+            session["tags"] = serde_json::json!(["game", "pvp"]);
+            session["headlessHost"] = true.into();
+        });
+        assert!(SessionFilter::default().matches_session(&busy));
+        assert!(SessionFilter { min_active_users: Some(3), max_active_users: Some(3), ..SessionFilter::default() }.matches_session(&busy));
+        assert!(!SessionFilter { min_active_users: Some(4), ..SessionFilter::default() }.matches_session(&busy));
+        assert!(!SessionFilter { max_active_users: Some(2), ..SessionFilter::default() }.matches_session(&busy));
+        assert!(SessionFilter { access_levels: vec!["anyone".to_string(), "friends".to_string()], ..SessionFilter::default() }.matches_session(&busy));
+        assert!(!SessionFilter { access_levels: vec!["Anyone".to_string()], ..SessionFilter::default() }.matches_session(&busy));
+        assert!(SessionFilter { tags: vec!["pvp".to_string(), "game".to_string()], ..SessionFilter::default() }.matches_session(&busy));
+        assert!(!SessionFilter { tags: vec!["pvp".to_string(), "chill".to_string()], ..SessionFilter::default() }.matches_session(&busy));
+        assert!(!SessionFilter { headless: Some(false), ..SessionFilter::default() }.matches_session(&busy));
+        assert!(!SessionFilter { mobile_friendly: Some(true), ..SessionFilter::default() }.matches_session(&busy));
+        assert!(!SessionFilter { neos_version: Some("2021.1".to_string()), ..SessionFilter::default() }.matches_session(&busy));
+    }
+
+    #[test]
+    fn filter_keys() {
+        let filter = |access_levels: &[&str], tags: &[&str]| SessionFilter {
+            access_levels: access_levels.iter().map(|level| level.to_string()).collect(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..SessionFilter::default()
+        };
+        assert_eq!(filter(&["Anyone", "friends"], &["pvp", "game"]).key(), filter(&["friends", "anyone"], &["game", "pvp", "game"]).key());
+        assert_ne!(filter(&[], &["pvp"]).key(), filter(&[], &["PvP"]).key());
+        assert_ne!(filter(&[], &["pvp"]).key(), SessionFilter::default().key());
+        assert_ne!(SessionFilter { patron: Some(true), ..SessionFilter::default() }.key(), SessionFilter::default().key());
+    }
+
+    #[test]
+    fn host_filters() {
+        let filter = SessionFilter { min_host_age_days: Some(7), max_host_age_days: Some(30), ..SessionFilter::default() };
+        assert!(filter.matches_host(Some(&host(7, false)), now()));
+        assert!(filter.matches_host(Some(&host(30, false)), now()));
+        assert!(!filter.matches_host(Some(&host(6, false)), now()));
+        assert!(!filter.matches_host(Some(&host(31, false)), now()));
+        // an unknown host only passes when no host filter is set
+        assert!(!filter.matches_host(None, now()));
+        assert!(SessionFilter::default().matches_host(None, now()));
+
+        let patrons = SessionFilter { patron: Some(true), ..SessionFilter::default() };
+        assert!(patrons.matches_host(Some(&host(100, true)), now()));
+        assert!(!patrons.matches_host(Some(&host(100, false)), now()));
+    }
+
+    fn entry(name: &str, uptime_hours: i64, active_users: i32, host_age_days: Option<i64>) -> SessionListEntry {
+        SessionListEntry {
+            session_id: format!("S-{}", name),
+            name: name.to_string(),
+            host_username: "host".to_string(),
+            host_user_id: None,
+            active_users,
+            joined_users: active_users,
+            session_begin_time: now() - Duration::hours(uptime_hours),
+            uptime_secs: uptime_hours * 3600,
+            host_present: true,
+            away_since: None,
+            is_new: false,
+            host: host_age_days.map(|days| SessionHost { registration_date: now() - Duration::days(days), is_patron: false }),
+            host_error: None,
+            new_users: None,
+        }
+    }
+
+    fn sorted(key: SortKey, descending: Option<bool>) -> Vec<String> {
+        let mut entries = vec![
+            entry("b", 3, 5, Some(10)),
+            entry("C", 1, 2, None),
+            entry("a", 2, 5, Some(1)),
+            entry("d", 4, 2, Some(10)),
+        ];
+        SessionSort { key, descending: descending.unwrap_or_else(|| key.default_descending()) }.sort(&mut entries);
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[test]
+    fn sorting() {
+        assert_eq!(sorted(SortKey::Uptime, None), ["C", "a", "b", "d"]);
+        assert_eq!(sorted(SortKey::Uptime, Some(true)), ["d", "b", "a", "C"]);
+        assert_eq!(sorted(SortKey::Name, None), ["a", "b", "C", "d"]);
+        // ties keep newest-first order, whichever way the key is sorted
+        assert_eq!(sorted(SortKey::ActiveUsers, None), ["a", "b", "C", "d"]);
+        assert_eq!(sorted(SortKey::ActiveUsers, Some(false)), ["C", "d", "a", "b"]);
+        // unknown hosts always go last
+        assert_eq!(sorted(SortKey::HostRegistration, None), ["a", "b", "d", "C"]);
+        assert_eq!(sorted(SortKey::HostRegistration, Some(false)), ["b", "d", "a", "C"]);
+    }
+}
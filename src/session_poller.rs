@@ -0,0 +1,154 @@
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+
+use crate::client::NeosApi;
+use crate::dto::session_dto::Session;
+use crate::duration_format::DurationFormat;
+use crate::error::Error;
+use crate::single_flight::SingleFlight;
+
+/// how often the background task refreshes the session list
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// snapshots older than this are refreshed on demand before being served
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(120);
+
+/// The full upstream session list as of a single fetch
+pub struct SessionSnapshot {
+    pub sessions: Vec<Session>,
+    pub fetched_at: Instant,
+}
+
+impl SessionSnapshot {
+    pub fn age(&self) -> Duration {
+        self.fetched_at.elapsed()
+    }
+}
+
+/// Keeps the latest session list in memory so handlers can answer without waiting on the Neos API
+pub struct SessionPoller {
+    neos_api: Arc<dyn NeosApi>,
+    max_age: Duration,
+    latest: RwLock<Option<Arc<SessionSnapshot>>>,
+    refreshes: SingleFlight<(), Result<Arc<SessionSnapshot>, Arc<Error>>>,
+    /// publishes every successfully fetched snapshot to streaming subscribers
+    updates: watch::Sender<Option<Arc<SessionSnapshot>>>,
+    /// kept so publishing never fails for want of a subscriber, and cloned for each new one
+    subscription: watch::Receiver<Option<Arc<SessionSnapshot>>>,
+}
+
+impl SessionPoller {
+    pub fn new(neos_api: Arc<dyn NeosApi>, max_age: Duration) -> Self {
+        let (updates, subscription) = watch::channel(None);
+        SessionPoller {
+            neos_api,
+            max_age,
+            latest: RwLock::new(None),
+            refreshes: SingleFlight::new(),
+            updates,
+            subscription,
+        }
+    }
+
+    /// Refresh the snapshot every `interval`, starting immediately. Must be called from within a Tokio runtime.
+    ///
+    /// The task only holds a weak reference between refreshes, so it stops once every other handle to the poller has
+    /// been dropped.
+    pub fn spawn(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let poller: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                let poller = match poller.upgrade() {
+                    Some(poller) => poller,
+                    None => break,
+                };
+                if let Err(e) = poller.refresh().await {
+                    eprintln!("Error polling session list: {}", e);
+                }
+            }
+        })
+    }
+
+    /// The latest snapshot, refreshed first if it is missing or older than the configured maximum age. If that refresh
+    /// fails a stale snapshot is still preferred over an error.
+    pub async fn snapshot(&self) -> Result<Arc<SessionSnapshot>, Error> {
+        let latest = self.latest.read().await.clone();
+        match latest {
+            Some(snapshot) if snapshot.age() <= self.max_age => Ok(snapshot),
+            Some(stale) => match self.refresh().await {
+                Ok(snapshot) => Ok(snapshot),
+                Err(e) => {
+                    eprintln!("Error refreshing stale session list, serving {} old snapshot: {}", DurationFormat::Compact.format(stale.age().as_secs() as i64), e);
+                    Ok(stale)
+                }
+            },
+            None => self.refresh().await,
+        }
+    }
+
+    /// Receive every new snapshot as it is fetched. The receiver may report the latest snapshot as changed once even if
+    /// it was fetched before subscribing.
+    pub fn subscribe(&self) -> watch::Receiver<Option<Arc<SessionSnapshot>>> {
+        self.subscription.clone()
+    }
+
+    /// Fetch a new snapshot. Concurrent refreshes, whether from the poller or from requests, share one download.
+    async fn refresh(&self) -> Result<Arc<SessionSnapshot>, Error> {
+        self.refreshes.run((), || async {
+            let sessions = self.neos_api.get_sessions().await.map_err(Arc::new)?;
+            let snapshot = Arc::new(SessionSnapshot {
+                sessions,
+                fetched_at: Instant::now(),
+            });
+            *self.latest.write().await = Some(snapshot.clone());
+            // the poller holds a receiver itself, so this cannot fail
+            let _ = self.updates.send(Some(snapshot.clone()));
+            Ok(snapshot)
+        }).await.map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+    use crate::client::fake::{self, FakeNeosApi};
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_download() {
+        let neos_api = Arc::new(FakeNeosApi::new(vec![fake::session("S-hub", "Neos Hub", &[("U-zkxs", true)])], vec![]));
+        *neos_api.latency.lock().unwrap() = Duration::from_millis(50);
+        let poller = Arc::new(SessionPoller::new(neos_api.clone(), DEFAULT_MAX_AGE));
+        let requests = (0..8).map(|_| {
+            let poller = poller.clone();
+            tokio::spawn(async move { poller.snapshot().await.map(|snapshot| snapshot.sessions.len()) })
+        }).collect::<Vec<_>>();
+        for request in requests {
+            assert_eq!(request.await.unwrap().unwrap(), 1);
+        }
+        assert_eq!(neos_api.session_fetches.load(Ordering::SeqCst), 1);
+
+        // a fresh snapshot is served without another download
+        poller.snapshot().await.unwrap();
+        assert_eq!(neos_api.session_fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn polling_stops_when_the_poller_is_dropped() {
+        let neos_api = Arc::new(FakeNeosApi::new(vec![], vec![]));
+        let poller = Arc::new(SessionPoller::new(neos_api.clone(), DEFAULT_MAX_AGE));
+        let task = poller.spawn(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(neos_api.session_fetches.load(Ordering::SeqCst) >= 1);
+
+        drop(poller);
+        tokio::time::timeout(Duration::from_secs(1), task).await
+            .expect("the polling task should stop")
+            .unwrap();
+    }
+}
@@ -0,0 +1,510 @@
+use std::{env, fmt, fs};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use app_dirs::{AppDataType, AppInfo};
+use serde::Deserialize;
+
+use crate::duration_format::DurationFormat;
+use crate::neos_api_url::NeosApiUrl;
+use crate::notification;
+use crate::rich_text::ColorRuleConfig;
+use crate::rooms;
+use crate::sanitize::SanitizePolicy;
+use crate::session_list::{SessionListConfig, SessionLists};
+use crate::session_poller;
+use crate::timers;
+
+const APP_INFO: AppInfo = AppInfo {
+    name: env!("CARGO_PKG_NAME"),
+    author: "runtime",
+};
+
+const SETTINGS_FILE_NAME: &str = "settings.toml";
+const CACHE_FILE_NAME: &str = "cache.json";
+const KV_STORE_FILE_NAME: &str = "kv.json";
+const LEADERBOARD_FILE_NAME: &str = "leaderboards.json";
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:3030";
+const DEFAULT_NEOS_API_BASE_URL: &str = "https://www.neosvr-api.com/api";
+
+const ENV_CONFIG: &str = "NEOS_API_CONFIG";
+const ENV_BIND: &str = "NEOS_API_BIND";
+const ENV_CACHE_FILE: &str = "NEOS_API_CACHE_FILE";
+const ENV_KV_STORE_FILE: &str = "NEOS_API_KV_STORE_FILE";
+const ENV_LEADERBOARD_FILE: &str = "NEOS_API_LEADERBOARD_FILE";
+const ENV_NEOS_API_BASE_URL: &str = "NEOS_API_BASE_URL";
+
+const USAGE: &str = "\
+Usage: neos-api [OPTIONS]
+
+Options:
+    --config <path>              settings file to load (env: NEOS_API_CONFIG)
+    --bind <address>             address to listen on, may be repeated (env: NEOS_API_BIND, comma separated)
+    --cache-file <path>          user cache file location (env: NEOS_API_CACHE_FILE)
+    --kv-store-file <path>       key-value store file location (env: NEOS_API_KV_STORE_FILE)
+    --leaderboard-file <path>    leaderboard file location (env: NEOS_API_LEADERBOARD_FILE)
+    --neos-api-base-url <url>    base URL of the Neos cloud API (env: NEOS_API_BASE_URL)
+    --help                       print this message and exit
+
+Command-line options take precedence over environment variables, which take
+precedence over the settings file.";
+
+/// Effective server configuration, after the settings file, environment and command line have been merged
+pub struct Settings {
+    /// the settings file that was loaded, if any
+    pub settings_file: Option<PathBuf>,
+    /// every address the server listens on
+    pub bind_addresses: Vec<SocketAddr>,
+    /// where the user cache is persisted
+    pub cache_file: PathBuf,
+    /// where the `/kv` store is persisted
+    pub kv_store_file: PathBuf,
+    /// where the `/leaderboard` boards are persisted
+    pub leaderboard_file: PathBuf,
+    /// base URL of the Neos cloud API
+    pub neos_api_base_url: NeosApiUrl,
+    /// how often the session list is refreshed in the background
+    pub session_poll_interval: Duration,
+    /// session lists older than this are refreshed before being served
+    pub session_max_age: Duration,
+    /// forget a client's `/sessionlist` notification state after it has not polled for this long
+    pub notification_expiry: Duration,
+    /// named session lists, including the default one
+    pub session_lists: SessionLists,
+    /// how names are cleaned up in text responses unless a request asks otherwise
+    pub text_sanitization: SanitizePolicy,
+    /// how session uptime is written unless a request asks otherwise
+    pub uptime_format: DurationFormat,
+    /// the most members any `/room` can have
+    pub room_max_members: usize,
+    /// how many recent messages each `/room` keeps for members who join later
+    pub room_history: usize,
+    /// how long an empty `/room` keeps its history
+    pub room_history_expiry: Duration,
+    /// how long an idle `/timer` is kept
+    pub timer_expiry: Duration,
+}
+
+/// Raw contents of the settings file. Every field is optional so a partial file only overrides what it mentions.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct SettingsFile {
+    bind_addresses: Option<Vec<String>>,
+    cache_file: Option<PathBuf>,
+    kv_store_file: Option<PathBuf>,
+    leaderboard_file: Option<PathBuf>,
+    neos_api_base_url: Option<String>,
+    session_poll_interval_secs: Option<u64>,
+    session_max_age_secs: Option<u64>,
+    notification_expiry_secs: Option<u64>,
+    #[serde(default)]
+    session_lists: BTreeMap<String, SessionListConfig>,
+    text_sanitization: Option<Vec<String>>,
+    /// session list line templates, by name
+    #[serde(default)]
+    templates: BTreeMap<String, String>,
+    color_rules: Option<Vec<ColorRuleConfig>>,
+    uptime_format: Option<String>,
+    room_max_members: Option<usize>,
+    room_history: Option<usize>,
+    room_history_expiry_secs: Option<u64>,
+    timer_expiry_secs: Option<u64>,
+}
+
+/// Overrides taken from the command line or from the environment
+#[derive(Default)]
+struct Overrides {
+    config: Option<PathBuf>,
+    bind_addresses: Option<Vec<String>>,
+    cache_file: Option<PathBuf>,
+    kv_store_file: Option<PathBuf>,
+    leaderboard_file: Option<PathBuf>,
+    neos_api_base_url: Option<String>,
+}
+
+/// Outcome of parsing the command line
+pub enum Startup {
+    Run(Box<Settings>),
+    Help,
+}
+
+impl Settings {
+    /// Load settings from the settings file, environment variables and command line arguments, in increasing order of
+    /// precedence.
+    pub fn load() -> Result<Startup, String> {
+        let cli = match parse_args(env::args().skip(1))? {
+            Some(cli) => cli,
+            None => return Ok(Startup::Help),
+        };
+        let env = read_env(env_var)?;
+
+        let config_dir = config_dir_path()?;
+
+        // an explicitly requested settings file must exist, but the default one is optional
+        let (settings_file_path, settings_file) = match cli.config.clone().or_else(|| env.config.clone()) {
+            Some(path) => {
+                let file = read_settings_file(&path)?;
+                (Some(path), file)
+            }
+            None => {
+                let path = config_dir.join(SETTINGS_FILE_NAME);
+                if path.is_file() {
+                    let file = read_settings_file(&path)?;
+                    (Some(path), file)
+                } else {
+                    (None, SettingsFile::default())
+                }
+            }
+        };
+
+        Ok(Startup::Run(Box::new(Settings::resolve(cli, env, settings_file_path, settings_file, &config_dir)?)))
+    }
+
+    // merge the command line, environment and settings file, in decreasing order of precedence, and check the result
+    fn resolve(cli: Overrides, env: Overrides, settings_file_path: Option<PathBuf>, settings_file: SettingsFile, config_dir: &Path) -> Result<Settings, String> {
+        let bind_addresses = cli.bind_addresses
+            .or(env.bind_addresses)
+            .or(settings_file.bind_addresses)
+            .unwrap_or_else(|| vec![DEFAULT_BIND_ADDRESS.to_string()]);
+        let bind_addresses = parse_bind_addresses(&bind_addresses)?;
+
+        let cache_file = cli.cache_file
+            .or(env.cache_file)
+            .or(settings_file.cache_file)
+            .unwrap_or_else(|| config_dir.join(CACHE_FILE_NAME));
+        if cache_file.is_dir() {
+            return Err(format!("cache file {} is a directory", cache_file.display()));
+        }
+
+        let kv_store_file = cli.kv_store_file
+            .or(env.kv_store_file)
+            .or(settings_file.kv_store_file)
+            .unwrap_or_else(|| config_dir.join(KV_STORE_FILE_NAME));
+        if kv_store_file.is_dir() {
+            return Err(format!("key-value store file {} is a directory", kv_store_file.display()));
+        }
+
+        let leaderboard_file = cli.leaderboard_file
+            .or(env.leaderboard_file)
+            .or(settings_file.leaderboard_file)
+            .unwrap_or_else(|| config_dir.join(LEADERBOARD_FILE_NAME));
+        if leaderboard_file.is_dir() {
+            return Err(format!("leaderboard file {} is a directory", leaderboard_file.display()));
+        }
+
+        let neos_api_base_url = cli.neos_api_base_url
+            .or(env.neos_api_base_url)
+            .or(settings_file.neos_api_base_url)
+            .unwrap_or_else(|| DEFAULT_NEOS_API_BASE_URL.to_string());
+        let neos_api_base_url = neos_api_base_url.parse::<NeosApiUrl>()?;
+
+        let session_poll_interval = positive_duration(
+            "session_poll_interval_secs",
+            settings_file.session_poll_interval_secs,
+            session_poller::DEFAULT_POLL_INTERVAL,
+        )?;
+        let session_max_age = positive_duration(
+            "session_max_age_secs",
+            settings_file.session_max_age_secs,
+            session_poller::DEFAULT_MAX_AGE,
+        )?;
+        let notification_expiry = positive_duration(
+            "notification_expiry_secs",
+            settings_file.notification_expiry_secs,
+            notification::DEFAULT_CLIENT_EXPIRY,
+        )?;
+        let session_lists = SessionLists::from_config(settings_file.session_lists, settings_file.templates, settings_file.color_rules)?;
+        let text_sanitization = match settings_file.text_sanitization {
+            Some(names) => SanitizePolicy::from_names(names.iter().map(String::as_str))
+                .map_err(|e| format!("invalid text_sanitization: {}", e))?,
+            None => SanitizePolicy::default(),
+        };
+        let uptime_format = match settings_file.uptime_format {
+            Some(name) => name.parse::<DurationFormat>().map_err(|e| format!("invalid uptime_format: {}", e))?,
+            None => DurationFormat::default(),
+        };
+        let room_max_members = match settings_file.room_max_members {
+            Some(0) => return Err("room_max_members must be greater than zero".to_string()),
+            room_max_members => room_max_members.unwrap_or(rooms::DEFAULT_MAX_MEMBERS),
+        };
+        let room_history = settings_file.room_history.unwrap_or(rooms::DEFAULT_HISTORY);
+        let room_history_expiry = positive_duration(
+            "room_history_expiry_secs",
+            settings_file.room_history_expiry_secs,
+            rooms::DEFAULT_HISTORY_EXPIRY,
+        )?;
+        let timer_expiry = positive_duration(
+            "timer_expiry_secs",
+            settings_file.timer_expiry_secs,
+            timers::DEFAULT_EXPIRY,
+        )?;
+
+        Ok(Settings {
+            settings_file: settings_file_path,
+            bind_addresses,
+            cache_file,
+            kv_store_file,
+            leaderboard_file,
+            neos_api_base_url,
+            session_poll_interval,
+            session_max_age,
+            notification_expiry,
+            session_lists,
+            text_sanitization,
+            uptime_format,
+            room_max_members,
+            room_history,
+            room_history_expiry,
+            timer_expiry,
+        })
+    }
+
+    pub fn usage() -> &'static str {
+        USAGE
+    }
+
+    pub fn default_neos_api_base_url() -> NeosApiUrl {
+        DEFAULT_NEOS_API_BASE_URL.parse().expect("default Neos API base URL should be valid")
+    }
+
+    /// `cache.json` in the per-user configuration directory, which is created if needed
+    pub fn default_cache_file() -> Result<PathBuf, String> {
+        Ok(config_dir_path()?.join(CACHE_FILE_NAME))
+    }
+
+    /// `kv.json` in the per-user configuration directory, which is created if needed
+    pub fn default_kv_store_file() -> Result<PathBuf, String> {
+        Ok(config_dir_path()?.join(KV_STORE_FILE_NAME))
+    }
+
+    /// `leaderboards.json` in the per-user configuration directory, which is created if needed
+    pub fn default_leaderboard_file() -> Result<PathBuf, String> {
+        Ok(config_dir_path()?.join(LEADERBOARD_FILE_NAME))
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.settings_file {
+            Some(path) => writeln!(f, "    settings file:     {}", path.display())?,
+            None => writeln!(f, "    settings file:     (none, using defaults)")?,
+        }
+        let bind_addresses = self.bind_addresses.iter()
+            .map(|address| address.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        writeln!(f, "    bind addresses:    {}", bind_addresses)?;
+        writeln!(f, "    cache file:        {}", self.cache_file.display())?;
+        writeln!(f, "    kv store file:     {}", self.kv_store_file.display())?;
+        writeln!(f, "    leaderboard file:  {}", self.leaderboard_file.display())?;
+        writeln!(f, "    Neos API base URL: {}", self.neos_api_base_url)?;
+        writeln!(f, "    session poll:      every {}", format_duration(self.session_poll_interval))?;
+        writeln!(f, "    session max age:   {}", format_duration(self.session_max_age))?;
+        writeln!(f, "    client expiry:     {}", format_duration(self.notification_expiry))?;
+        writeln!(f, "    session lists:     {}", self.session_lists.names().collect::<Vec<&str>>().join(", "))?;
+        writeln!(f, "    templates:         {}", self.session_lists.template_names().collect::<Vec<&str>>().join(", "))?;
+        writeln!(f, "    color rules:       {}", self.session_lists.color_rules.len())?;
+        writeln!(f, "    text sanitization: {}", self.text_sanitization)?;
+        writeln!(f, "    uptime format:     {}", self.uptime_format)?;
+        writeln!(f, "    rooms:             up to {} members, {} retained messages for {}", self.room_max_members, self.room_history, format_duration(self.room_history_expiry))?;
+        write!(f, "    timer expiry:      {}", format_duration(self.timer_expiry))
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    DurationFormat::Compact.format(duration.as_secs() as i64)
+}
+
+fn parse_args<I: Iterator<Item=String>>(mut args: I) -> Result<Option<Overrides>, String> {
+    let mut overrides = Overrides::default();
+    while let Some(arg) = args.next() {
+        // support both "--flag value" and "--flag=value"
+        let (flag, inline_value) = match arg.find('=') {
+            Some(index) if arg.starts_with("--") => (arg[..index].to_string(), Some(arg[index + 1..].to_string())),
+            _ => (arg, None),
+        };
+        if flag == "--help" || flag == "-h" {
+            return Ok(None);
+        }
+        let mut value = || inline_value.clone()
+            .or_else(|| args.next())
+            .ok_or_else(|| format!("missing value for {}", flag));
+        match flag.as_str() {
+            "--config" => overrides.config = Some(PathBuf::from(value()?)),
+            "--bind" => overrides.bind_addresses.get_or_insert_with(Vec::new).push(value()?),
+            "--cache-file" => overrides.cache_file = Some(PathBuf::from(value()?)),
+            "--kv-store-file" => overrides.kv_store_file = Some(PathBuf::from(value()?)),
+            "--leaderboard-file" => overrides.leaderboard_file = Some(PathBuf::from(value()?)),
+            "--neos-api-base-url" => overrides.neos_api_base_url = Some(value()?),
+            _ => return Err(format!("unrecognized argument \"{}\"\n\n{}", flag, USAGE)),
+        }
+    }
+    Ok(Some(overrides))
+}
+
+// `env_var` looks up a variable, so that tests need not touch the real environment
+fn read_env<F>(env_var: F) -> Result<Overrides, String>
+    where F: Fn(&str) -> Result<Option<String>, String>
+{
+    Ok(Overrides {
+        config: env_var(ENV_CONFIG)?.map(PathBuf::from),
+        bind_addresses: env_var(ENV_BIND)?.map(|value| {
+            value.split(',')
+                .map(|address| address.trim().to_string())
+                .filter(|address| !address.is_empty())
+                .collect()
+        }),
+        cache_file: env_var(ENV_CACHE_FILE)?.map(PathBuf::from),
+        kv_store_file: env_var(ENV_KV_STORE_FILE)?.map(PathBuf::from),
+        leaderboard_file: env_var(ENV_LEADERBOARD_FILE)?.map(PathBuf::from),
+        neos_api_base_url: env_var(ENV_NEOS_API_BASE_URL)?,
+    })
+}
+
+// an unset or empty variable is treated as absent
+fn env_var(name: &str) -> Result<Option<String>, String> {
+    match env::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(format!("invalid value for environment variable {}: {}", name, e)),
+    }
+}
+
+fn read_settings_file(path: &Path) -> Result<SettingsFile, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("could not read settings file {}: {}", path.display(), e))?;
+    toml::from_str(&contents)
+        .map_err(|e| format!("could not parse settings file {}: {}", path.display(), e))
+}
+
+fn parse_bind_addresses(addresses: &[String]) -> Result<Vec<SocketAddr>, String> {
+    if addresses.is_empty() {
+        return Err("at least one bind address is required".to_string());
+    }
+    let mut parsed: Vec<SocketAddr> = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let socket_address = address.parse::<SocketAddr>()
+            .map_err(|e| format!("invalid bind address \"{}\": {} (expected e.g. 127.0.0.1:3030 or [::1]:3030)", address, e))?;
+        if !parsed.contains(&socket_address) {
+            parsed.push(socket_address);
+        }
+    }
+    Ok(parsed)
+}
+
+fn positive_duration(name: &str, seconds: Option<u64>, default: Duration) -> Result<Duration, String> {
+    match seconds {
+        Some(0) => Err(format!("{} must be greater than zero", name)),
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+        None => Ok(default),
+    }
+}
+
+fn config_dir_path() -> Result<PathBuf, String> {
+    let config_dir_path = app_dirs::get_app_root(AppDataType::UserConfig, &APP_INFO)
+        .map_err(|e| format!("unable to locate configuration directory: {}", e))?;
+    fs::create_dir_all(config_dir_path.as_path())
+        .map_err(|e| format!("failed to create configuration directory {}: {}", config_dir_path.display(), e))?;
+    Ok(config_dir_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Option<Overrides>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Overrides {
+        let vars = vars.iter().cloned().collect::<HashMap<&str, &str>>();
+        read_env(|name| Ok(vars.get(name).filter(|value| !value.is_empty()).map(|value| value.to_string()))).unwrap()
+    }
+
+    fn resolve(cli: Overrides, env: Overrides, settings_file: &str) -> Result<Settings, String> {
+        let settings_file = toml::from_str::<SettingsFile>(settings_file).map_err(|e| e.to_string())?;
+        Settings::resolve(cli, env, None, settings_file, Path::new("config"))
+    }
+
+    #[test]
+    fn command_line() {
+        let cli = args(&["--bind", "127.0.0.1:1", "--bind=[::1]:2", "--cache-file=cache.json", "--kv-store-file", "kv.json"]).unwrap().unwrap();
+        assert_eq!(cli.bind_addresses, Some(vec!["127.0.0.1:1".to_string(), "[::1]:2".to_string()]));
+        assert_eq!(cli.cache_file, Some(PathBuf::from("cache.json")));
+        assert_eq!(cli.kv_store_file, Some(PathBuf::from("kv.json")));
+        assert_eq!(cli.leaderboard_file, None);
+
+        assert!(args(&["--bind", "127.0.0.1:1", "--help"]).unwrap().is_none());
+        assert_eq!(args(&["--config"]).err().unwrap(), "missing value for --config");
+        assert!(args(&["--port", "80"]).err().unwrap().starts_with("unrecognized argument \"--port\""));
+    }
+
+    #[test]
+    fn environment() {
+        let env = env(&[(ENV_BIND, " 127.0.0.1:1, ,[::1]:2"), (ENV_LEADERBOARD_FILE, "boards.json"), (ENV_CACHE_FILE, "")]);
+        assert_eq!(env.bind_addresses, Some(vec!["127.0.0.1:1".to_string(), "[::1]:2".to_string()]));
+        assert_eq!(env.leaderboard_file, Some(PathBuf::from("boards.json")));
+        // empty variables count as unset
+        assert_eq!(env.cache_file, None);
+    }
+
+    #[test]
+    fn command_line_over_environment_over_file() {
+        let file = "bind_addresses = [\"127.0.0.1:3\"]\ncache_file = \"file.json\"\nkv_store_file = \"file-kv.json\"\nleaderboard_file = \"file-boards.json\"\nneos_api_base_url = \"http://file/api\"";
+        let settings = resolve(
+            args(&["--cache-file", "cli.json", "--neos-api-base-url", "http://cli/api"]).unwrap().unwrap(),
+            env(&[(ENV_CACHE_FILE, "env.json"), (ENV_KV_STORE_FILE, "env-kv.json"), (ENV_NEOS_API_BASE_URL, "http://env/api")]),
+            file,
+        ).unwrap();
+        assert_eq!(settings.cache_file, PathBuf::from("cli.json"));
+        assert_eq!(settings.neos_api_base_url.to_string(), "http://cli/api");
+        assert_eq!(settings.kv_store_file, PathBuf::from("env-kv.json"));
+        assert_eq!(settings.leaderboard_file, PathBuf::from("file-boards.json"));
+        assert_eq!(settings.bind_addresses, vec!["127.0.0.1:3".parse::<SocketAddr>().unwrap()]);
+
+        // with nothing set, files go in the configuration directory
+        let settings = resolve(Overrides::default(), Overrides::default(), "").unwrap();
+        assert_eq!(settings.bind_addresses, vec![DEFAULT_BIND_ADDRESS.parse::<SocketAddr>().unwrap()]);
+        assert_eq!(settings.cache_file, Path::new("config").join(CACHE_FILE_NAME));
+        assert_eq!(settings.kv_store_file, Path::new("config").join(KV_STORE_FILE_NAME));
+        assert_eq!(settings.leaderboard_file, Path::new("config").join(LEADERBOARD_FILE_NAME));
+        assert_eq!(settings.neos_api_base_url.to_string(), DEFAULT_NEOS_API_BASE_URL);
+        assert_eq!(settings.session_poll_interval, session_poller::DEFAULT_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn bind_addresses() {
+        let addresses = ["127.0.0.1:3030", "[::1]:3030", "127.0.0.1:3030"].map(String::from);
+        assert_eq!(parse_bind_addresses(&addresses).unwrap().len(), 2);
+        assert_eq!(parse_bind_addresses(&[]).unwrap_err(), "at least one bind address is required");
+        assert!(parse_bind_addresses(&["localhost:3030".to_string()]).unwrap_err().starts_with("invalid bind address \"localhost:3030\""));
+        assert!(parse_bind_addresses(&["127.0.0.1".to_string()]).is_err());
+    }
+
+    #[test]
+    fn durations() {
+        let default = Duration::from_secs(30);
+        assert_eq!(positive_duration("poll_secs", None, default), Ok(default));
+        assert_eq!(positive_duration("poll_secs", Some(5), default), Ok(Duration::from_secs(5)));
+        assert_eq!(positive_duration("poll_secs", Some(0), default), Err("poll_secs must be greater than zero".to_string()));
+    }
+
+    #[test]
+    fn invalid_values() {
+        let invalid = |cli: &[&str], file: &str| resolve(args(cli).unwrap().unwrap(), Overrides::default(), file).err().unwrap();
+        assert!(invalid(&["--bind", "nowhere"], "").starts_with("invalid bind address \"nowhere\""));
+        assert!(invalid(&[], "bind_addresses = []").starts_with("at least one bind address"));
+        assert!(invalid(&["--neos-api-base-url", "ftp://example.com"], "").contains("unsupported scheme \"ftp\""));
+        assert_eq!(invalid(&[], "session_max_age_secs = 0"), "session_max_age_secs must be greater than zero");
+        assert_eq!(invalid(&[], "room_max_members = 0"), "room_max_members must be greater than zero");
+        assert!(invalid(&[], "uptime_format = \"fortnights\"").starts_with("invalid uptime_format"));
+        assert!(invalid(&[], "text_sanitization = [\"shout\"]").starts_with("invalid text_sanitization"));
+        assert!(invalid(&[], "listen = \"127.0.0.1:3030\"").contains("unknown field `listen`"));
+        assert!(invalid(&["--cache-file", "."], "").ends_with("is a directory"));
+    }
+}
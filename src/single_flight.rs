@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Mutex;
+
+use tokio::sync::oneshot;
+
+/// Registry of in-flight fetches. Concurrent callers asking for the same key share a single fetch and all receive a
+/// clone of its result.
+pub struct SingleFlight<K, V> {
+    in_flight: Mutex<HashMap<K, Vec<oneshot::Sender<V>>>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    pub fn new() -> Self {
+        SingleFlight {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run `fetch` unless a fetch for `key` is already in flight, in which case wait for that one instead. If the
+    /// caller driving the shared fetch is cancelled, one of the waiters takes over.
+    pub async fn run<F, Fut>(&self, key: K, fetch: F) -> V
+        where F: FnOnce() -> Fut,
+              Fut: Future<Output=V>
+    {
+        let mut fetch = Some(fetch);
+        loop {
+            let receiver = {
+                let mut in_flight = self.in_flight.lock().expect("single flight registry poisoned");
+                match in_flight.get_mut(&key) {
+                    Some(waiters) => {
+                        let (sender, receiver) = oneshot::channel();
+                        waiters.push(sender);
+                        Some(receiver)
+                    }
+                    None => {
+                        in_flight.insert(key.clone(), Vec::new());
+                        None
+                    }
+                }
+            };
+
+            match receiver {
+                Some(receiver) => match receiver.await {
+                    Ok(value) => return value,
+                    // the leader was dropped before finishing, so try again
+                    Err(_) => continue,
+                },
+                None => {
+                    let leader = Leader { single_flight: self, key: Some(key) };
+                    let fetch = fetch.take().expect("a caller only leads a fetch once");
+                    let value = fetch().await;
+                    for waiter in leader.finish() {
+                        // a waiter that has gone away no longer needs the value
+                        let _ = waiter.send(value.clone());
+                    }
+                    return value;
+                }
+            }
+        }
+    }
+}
+
+/// Removes the registry entry when the leading fetch finishes or is dropped
+struct Leader<'a, K: Eq + Hash, V> {
+    single_flight: &'a SingleFlight<K, V>,
+    key: Option<K>,
+}
+
+impl<K: Eq + Hash, V> Leader<'_, K, V> {
+    fn finish(mut self) -> Vec<oneshot::Sender<V>> {
+        self.remove()
+    }
+
+    fn remove(&mut self) -> Vec<oneshot::Sender<V>> {
+        match self.key.take() {
+            Some(key) => self.single_flight.in_flight.lock()
+                .expect("single flight registry poisoned")
+                .remove(&key)
+                .unwrap_or_default(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        // dropping the senders wakes the waiters, and one of them takes over
+        self.remove();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_fetch() {
+        let single_flight = Arc::new(SingleFlight::<&str, usize>::new());
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let callers = (0..8).map(|_| {
+            let single_flight = single_flight.clone();
+            let fetches = fetches.clone();
+            tokio::spawn(async move {
+                single_flight.run("key", || async {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    fetches.fetch_add(1, Ordering::SeqCst) + 1
+                }).await
+            })
+        }).collect::<Vec<_>>();
+        for caller in callers {
+            assert_eq!(caller.await.unwrap(), 1);
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert!(single_flight.in_flight.lock().unwrap().is_empty());
+
+        // once finished, the next caller fetches again
+        assert_eq!(single_flight.run("key", || async { 2 }).await, 2);
+    }
+
+    #[tokio::test]
+    async fn different_keys_fetch_separately() {
+        let single_flight = SingleFlight::<&str, &str>::new();
+        let (a, b) = tokio::join!(
+            single_flight.run("a", || async { "a" }),
+            single_flight.run("b", || async { "b" }),
+        );
+        assert_eq!((a, b), ("a", "b"));
+    }
+
+    #[tokio::test]
+    async fn a_waiter_takes_over_from_a_cancelled_leader() {
+        let single_flight = Arc::new(SingleFlight::<&str, &str>::new());
+        let (started, leader_started) = oneshot::channel();
+        let leader = tokio::spawn({
+            let single_flight = single_flight.clone();
+            async move {
+                single_flight.run("key", || async move {
+                    let _ = started.send(());
+                    std::future::pending::<&str>().await
+                }).await
+            }
+        });
+        leader_started.await.unwrap();
+
+        let waiter = tokio::spawn({
+            let single_flight = single_flight.clone();
+            async move { single_flight.run("key", || async { "from the waiter" }).await }
+        });
+        while single_flight.in_flight.lock().unwrap().get("key").is_none_or(|waiters| waiters.is_empty()) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        leader.abort();
+        let value = tokio::time::timeout(Duration::from_secs(5), waiter).await
+            .expect("the waiter should not wait forever for a cancelled leader")
+            .unwrap();
+        assert_eq!(value, "from the waiter");
+        assert!(single_flight.in_flight.lock().unwrap().is_empty());
+    }
+}
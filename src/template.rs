@@ -0,0 +1,319 @@
+use std::str::FromStr;
+
+/// A session list line template, e.g. `{host:<16} {world}{?patron} (patron){/patron}`.
+///
+/// - `{field}` inserts a field. `{field:<20}`, `{field:>20}` and `{field:^20}` pad it to 20 characters, aligned left,
+///   right or centered, and `{field:.12}` or `{field:<20.12}` truncate it to 12 characters first.
+/// - `{?field}...{/field}` only renders its contents if the field has a value, and `{!field}...{/field}` only if it
+///   does not. These can be nested.
+/// - `{{` and `}}` are literal braces.
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+/// Everything a session list line can show
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Field {
+    SessionId,
+    Host,
+    World,
+    Active,
+    Joined,
+    Uptime,
+    UptimeSecs,
+    /// host registration date
+    Registered,
+    /// `patron` if the host is a patron, otherwise no value
+    Patron,
+    /// host account age
+    AgeDays,
+    HostError,
+    /// in new-user lists, the youngest account that put the session on the list
+    NewUser,
+    NewUserAgeDays,
+}
+
+enum Node {
+    Text(String),
+    Field {
+        field: Field,
+        format: FieldFormat,
+    },
+    Conditional {
+        field: Field,
+        negated: bool,
+        body: Vec<Node>,
+    },
+}
+
+#[derive(Default)]
+struct FieldFormat {
+    align: Align,
+    width: usize,
+    max_width: Option<usize>,
+}
+
+#[derive(Clone, Copy, Default)]
+enum Align {
+    #[default]
+    Left,
+    Right,
+    Center,
+}
+
+/// the line format `/sessionlist` has always used
+pub const DEFAULT_TEMPLATE: &str = "{host} ({world}) ({active}/{joined}) {uptime}{?registered} {registered}{/registered}{?patron} patron{/patron}{?host_error} {host_error}{/host_error}{?new_user} {new_user_age_days}d {new_user}{/new_user}";
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "session_id" => Some(Field::SessionId),
+            "host" => Some(Field::Host),
+            "world" => Some(Field::World),
+            "active" => Some(Field::Active),
+            "joined" => Some(Field::Joined),
+            "uptime" => Some(Field::Uptime),
+            "uptime_secs" => Some(Field::UptimeSecs),
+            "registered" => Some(Field::Registered),
+            "patron" => Some(Field::Patron),
+            "age_days" => Some(Field::AgeDays),
+            "host_error" => Some(Field::HostError),
+            "new_user" => Some(Field::NewUser),
+            "new_user_age_days" => Some(Field::NewUserAgeDays),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::SessionId => "session_id",
+            Field::Host => "host",
+            Field::World => "world",
+            Field::Active => "active",
+            Field::Joined => "joined",
+            Field::Uptime => "uptime",
+            Field::UptimeSecs => "uptime_secs",
+            Field::Registered => "registered",
+            Field::Patron => "patron",
+            Field::AgeDays => "age_days",
+            Field::HostError => "host_error",
+            Field::NewUser => "new_user",
+            Field::NewUserAgeDays => "new_user_age_days",
+        }
+    }
+}
+
+impl Template {
+    /// Render with the given field values. A field without a value renders as an empty string.
+    pub fn render(&self, values: impl Fn(Field) -> Option<String>) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, &values, &mut output);
+        output
+    }
+}
+
+fn render_nodes(nodes: &[Node], values: &impl Fn(Field) -> Option<String>, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Field { field, format } => format.write(values(*field).unwrap_or_default(), output),
+            Node::Conditional { field, negated, body } => {
+                if values(*field).is_some() != *negated {
+                    render_nodes(body, values, output);
+                }
+            }
+        }
+    }
+}
+
+impl FieldFormat {
+    fn write(&self, value: String, output: &mut String) {
+        let value = match self.max_width {
+            Some(max_width) => value.chars().take(max_width).collect(),
+            None => value,
+        };
+        let padding = self.width.saturating_sub(value.chars().count());
+        let (before, after) = match self.align {
+            Align::Left => (0, padding),
+            Align::Right => (padding, 0),
+            Align::Center => (padding / 2, padding - padding / 2),
+        };
+        output.extend(std::iter::repeat_n(' ', before));
+        output.push_str(&value);
+        output.extend(std::iter::repeat_n(' ', after));
+    }
+
+    fn parse(spec: &str) -> Result<Self, String> {
+        let (align, rest) = match spec.chars().next() {
+            Some('<') => (Align::Left, &spec[1..]),
+            Some('>') => (Align::Right, &spec[1..]),
+            Some('^') => (Align::Center, &spec[1..]),
+            _ => (Align::Left, spec),
+        };
+        let (width, max_width) = match rest.split_once('.') {
+            Some((width, max_width)) => (width, Some(max_width)),
+            None => (rest, None),
+        };
+        let width = if width.is_empty() {
+            0
+        } else {
+            width.parse::<usize>().map_err(|_| format!("invalid width \"{}\" in format \"{}\"", width, spec))?
+        };
+        let max_width = max_width
+            .map(|max_width| max_width.parse::<usize>().map_err(|_| format!("invalid maximum width \"{}\" in format \"{}\"", max_width, spec)))
+            .transpose()?;
+        Ok(FieldFormat { align, width, max_width })
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        // each open conditional, with the nodes before it
+        let mut stack: Vec<(Field, bool, Vec<Node>)> = Vec::new();
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.char_indices().peekable();
+
+        while let Some((position, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek().map(|(_, c)| *c) == Some('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => return Err(format!("unmatched \"}}\" at position {} (use \"}}}}\" for a literal brace)", position)),
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((_, c)) => tag.push(c),
+                            None => return Err(format!("unclosed \"{{\" at position {}", position)),
+                        }
+                    }
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    if let Some(name) = tag.strip_prefix('?').or_else(|| tag.strip_prefix('!')) {
+                        let field = parse_field(name, position)?;
+                        stack.push((field, tag.starts_with('!'), std::mem::take(&mut nodes)));
+                    } else if let Some(name) = tag.strip_prefix('/') {
+                        let field = parse_field(name, position)?;
+                        match stack.pop() {
+                            Some((open_field, negated, outer)) if open_field == field => {
+                                let body = std::mem::replace(&mut nodes, outer);
+                                nodes.push(Node::Conditional { field, negated, body });
+                            }
+                            Some((open_field, _, _)) => return Err(format!(
+                                "\"{{/{}}}\" at position {} does not close the open \"{}\" conditional", name, position, open_field.name()
+                            )),
+                            None => return Err(format!("\"{{/{}}}\" at position {} has no matching conditional", name, position)),
+                        }
+                    } else {
+                        let (name, format) = match tag.split_once(':') {
+                            Some((name, spec)) => (name, FieldFormat::parse(spec).map_err(|e| format!("{} at position {}", e, position))?),
+                            None => (tag.as_str(), FieldFormat::default()),
+                        };
+                        let field = parse_field(name, position)?;
+                        nodes.push(Node::Field { field, format });
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+
+        if let Some((field, negated, _)) = stack.last() {
+            return Err(format!("unclosed \"{{{}{}}}\" conditional", if *negated { '!' } else { '?' }, field.name()));
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(Template { nodes })
+    }
+}
+
+fn parse_field(name: &str, position: usize) -> Result<Field, String> {
+    Field::from_name(name.trim()).ok_or_else(|| format!("unknown field \"{}\" at position {}", name, position))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, values: &[(Field, &str)]) -> String {
+        let template = template.parse::<Template>().expect("template should parse");
+        template.render(|field| values.iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, value)| value.to_string()))
+    }
+
+    fn parse_error(template: &str) -> String {
+        match template.parse::<Template>() {
+            Ok(_) => panic!("\"{}\" should not parse", template),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn fields_and_text() {
+        assert_eq!(render("{host} ({world})", &[(Field::Host, "zkxs"), (Field::World, "Cafe")]), "zkxs (Cafe)");
+        assert_eq!(render("{ host }", &[(Field::Host, "zkxs")]), "zkxs");
+        assert_eq!(render("[{registered}]", &[]), "[]");
+        assert_eq!(render("{{host}} }}", &[(Field::Host, "zkxs")]), "{host} }");
+    }
+
+    #[test]
+    fn padding_and_truncation() {
+        let values = [(Field::Host, "zkxs")];
+        assert_eq!(render("[{host:6}]", &values), "[zkxs  ]");
+        assert_eq!(render("[{host:<6}]", &values), "[zkxs  ]");
+        assert_eq!(render("[{host:>6}]", &values), "[  zkxs]");
+        assert_eq!(render("[{host:^7}]", &values), "[ zkxs  ]");
+        assert_eq!(render("[{host:.2}]", &values), "[zk]");
+        assert_eq!(render("[{host:>4.2}]", &values), "[  zk]");
+        assert_eq!(render("[{host:2}]", &values), "[zkxs]");
+        // widths count characters, not bytes
+        assert_eq!(render("[{host:>3.2}]", &[(Field::Host, "ÅÄÖ")]), "[ ÅÄ]");
+    }
+
+    #[test]
+    fn conditionals() {
+        let template = "{host}{?patron} patron{/patron}{!registered} unregistered{/registered}";
+        assert_eq!(render(template, &[(Field::Host, "a"), (Field::Patron, "patron")]), "a patron unregistered");
+        assert_eq!(render(template, &[(Field::Host, "a"), (Field::Registered, "2020")]), "a");
+        // an empty value still counts as present
+        assert_eq!(render("{?host_error}!{/host_error}", &[(Field::HostError, "")]), "!");
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let template = "{?new_user}[{new_user}{?new_user_age_days} {new_user_age_days}d{/new_user_age_days}]{/new_user}";
+        assert_eq!(render(template, &[]), "");
+        assert_eq!(render(template, &[(Field::NewUser, "x")]), "[x]");
+        assert_eq!(render(template, &[(Field::NewUser, "x"), (Field::NewUserAgeDays, "3")]), "[x 3d]");
+        assert_eq!(render(template, &[(Field::NewUserAgeDays, "3")]), "");
+    }
+
+    #[test]
+    fn default_template_parses() {
+        assert!(DEFAULT_TEMPLATE.parse::<Template>().is_ok());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_error("{host"), "unclosed \"{\" at position 0");
+        assert_eq!(parse_error("a}b"), "unmatched \"}\" at position 1 (use \"}}\" for a literal brace)");
+        assert_eq!(parse_error("{nope}"), "unknown field \"nope\" at position 0");
+        assert_eq!(parse_error("{?patron}x"), "unclosed \"{?patron}\" conditional");
+        assert_eq!(parse_error("{!patron}x"), "unclosed \"{!patron}\" conditional");
+        assert_eq!(parse_error("{/patron}"), "\"{/patron}\" at position 0 has no matching conditional");
+        assert_eq!(parse_error("{?patron}{?host}{/patron}{/host}"), "\"{/patron}\" at position 16 does not close the open \"host\" conditional");
+        assert_eq!(parse_error("{host:x}"), "invalid width \"x\" in format \"x\" at position 0");
+        assert_eq!(parse_error("{host:<4.y}"), "invalid maximum width \"y\" in format \"<4.y\" at position 0");
+    }
+}
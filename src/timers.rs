@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::broadcast;
+
+use crate::error::Error;
+use crate::names::check_name;
+
+/// longest target, and furthest from now an end time can be, in milliseconds: about 100 years
+pub const MAX_TARGET_MS: i64 = 100 * 365 * 24 * 60 * 60 * 1000;
+/// how long a timer is kept after its last change, or after it finishes counting down, unless configured otherwise
+pub const DEFAULT_EXPIRY: StdDuration = StdDuration::from_secs(24 * 60 * 60);
+/// how many changes a slow subscriber can fall behind by before it is resynchronized
+const CHANGE_BUFFER: usize = 64;
+
+/// A named stopwatch that can also count down to a target
+#[derive(Clone)]
+pub struct Timer {
+    pub status: TimerStatus,
+    /// time counted before the current run, or all counted time if not running
+    accumulated: Duration,
+    /// how long the timer counts down from, if it has a target
+    pub target: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimerStatus {
+    #[default]
+    Stopped,
+    Running {
+        since: DateTime<Utc>,
+    },
+    Paused,
+}
+
+/// What a timer request does. Each is a no-op if the timer is not in a state it applies to, so several world instances
+/// can send the same request without disturbing each other.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TimerOperation {
+    Get,
+    /// start from zero if stopped; the first caller wins, like `/initTime`
+    Start,
+    /// start from zero whatever the state
+    Restart,
+    Pause,
+    Resume,
+    /// stop and go back to zero, keeping the target
+    Reset,
+    /// only change the target
+    Target,
+}
+
+/// the `POST /timer/{name}/{operation}` path segment
+impl FromStr for TimerOperation {
+    type Err = ();
+
+    fn from_str(operation: &str) -> Result<Self, Self::Err> {
+        match operation {
+            "start" => Ok(TimerOperation::Start),
+            "restart" => Ok(TimerOperation::Restart),
+            "pause" => Ok(TimerOperation::Pause),
+            "resume" => Ok(TimerOperation::Resume),
+            "reset" => Ok(TimerOperation::Reset),
+            "target" => Ok(TimerOperation::Target),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How a request sets a timer's target, if it does
+#[derive(Clone, Copy)]
+pub enum TargetChange {
+    /// count down from this long
+    Duration(Duration),
+    /// finish at this time, which is converted to a duration when the request arrives; a time in the past finishes
+    /// the timer straight away
+    EndsAt(DateTime<Utc>),
+    Clear,
+}
+
+/// Every timer, by name. Timers spring into existence, stopped, the first time they are changed, and are forgotten
+/// once they are reset with no target or have sat idle past the expiry. Forgetting a timer looks the same to clients
+/// as a stopped timer at zero, so it is not broadcast.
+pub struct Timers {
+    timers: Mutex<HashMap<String, TimerEntry>>,
+    expiry: Duration,
+    changes: broadcast::Sender<(String, Timer)>,
+}
+
+struct TimerEntry {
+    timer: Timer,
+    changed_at: DateTime<Utc>,
+}
+
+/// stopped at zero, with no target
+impl Default for Timer {
+    fn default() -> Self {
+        Timer {
+            status: TimerStatus::Stopped,
+            accumulated: Duration::zero(),
+            target: None,
+        }
+    }
+}
+
+impl Timer {
+    pub fn elapsed(&self, now: DateTime<Utc>) -> Duration {
+        match self.status {
+            TimerStatus::Running { since } => self.accumulated + (now - since),
+            TimerStatus::Stopped | TimerStatus::Paused => self.accumulated,
+        }
+    }
+
+    /// time left until the target, never negative
+    pub fn remaining(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.target.map(|target| (target - self.elapsed(now)).max(Duration::zero()))
+    }
+
+    /// when a running timer reaches its target
+    pub fn ends_at(&self) -> Option<DateTime<Utc>> {
+        match (self.status, self.target) {
+            (TimerStatus::Running { since }, Some(target)) => since.checked_add_signed(target - self.accumulated),
+            _ => None,
+        }
+    }
+
+    pub fn status_name(&self) -> &'static str {
+        match self.status {
+            TimerStatus::Stopped => "stopped",
+            TimerStatus::Running { .. } => "running",
+            TimerStatus::Paused => "paused",
+        }
+    }
+
+    // apply an operation, returning whether anything changed
+    fn apply(&mut self, operation: TimerOperation, target: Option<TargetChange>, now: DateTime<Utc>) -> bool {
+        let before = (self.status, self.accumulated, self.target);
+        match (operation, self.status) {
+            (TimerOperation::Start, TimerStatus::Stopped) | (TimerOperation::Restart, _) => {
+                self.status = TimerStatus::Running { since: now };
+                self.accumulated = Duration::zero();
+            }
+            (TimerOperation::Pause, TimerStatus::Running { since }) => {
+                self.accumulated = self.accumulated + (now - since);
+                self.status = TimerStatus::Paused;
+            }
+            (TimerOperation::Resume, TimerStatus::Paused) => self.status = TimerStatus::Running { since: now },
+            (TimerOperation::Reset, _) => {
+                self.status = TimerStatus::Stopped;
+                self.accumulated = Duration::zero();
+            }
+            _ => {}
+        }
+        // a target is only taken from requests that changed the timer, or that are only about the target
+        let changed = (self.status, self.accumulated, self.target) != before;
+        if let Some(target) = target.filter(|_| changed || operation == TimerOperation::Target) {
+            self.target = match target {
+                TargetChange::Duration(duration) => Some(duration),
+                TargetChange::EndsAt(ends_at) => Some(self.elapsed(now) + (ends_at - now).max(Duration::zero())),
+                TargetChange::Clear => None,
+            };
+        }
+        changed || self.target != before.2
+    }
+}
+
+impl TimerEntry {
+    // idle since its last change, or since it finished counting down if that is later
+    fn is_expired(&self, expiry: Duration, now: DateTime<Utc>) -> bool {
+        let idle_since = self.timer.ends_at().map_or(self.changed_at, |ends_at| ends_at.max(self.changed_at));
+        now - idle_since > expiry
+    }
+}
+
+impl Timers {
+    /// `expiry` is how long a timer is kept once nothing has changed it and it is not counting down
+    pub fn new(expiry: StdDuration) -> Self {
+        Timers {
+            timers: Mutex::new(HashMap::new()),
+            expiry: Duration::from_std(expiry).unwrap_or_else(|_| Duration::max_value()),
+            changes: broadcast::channel(CHANGE_BUFFER).0,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Timer, Error> {
+        self.apply(name, TimerOperation::Get, None)
+    }
+
+    /// Apply an operation and return the timer's new state. Subscribers are told about any change.
+    pub fn apply(&self, name: &str, operation: TimerOperation, target: Option<TargetChange>) -> Result<Timer, Error> {
+        self.apply_at(name, operation, target, Utc::now())
+    }
+
+    fn apply_at(&self, name: &str, operation: TimerOperation, target: Option<TargetChange>, now: DateTime<Utc>) -> Result<Timer, Error> {
+        check_name("timer name", name)?;
+        let mut timers = self.timers.lock().expect("timers poisoned");
+        timers.retain(|_, entry| !entry.is_expired(self.expiry, now));
+        let mut timer = timers.get(name).map(|entry| entry.timer.clone()).unwrap_or_default();
+        if operation == TimerOperation::Get || !timer.apply(operation, target, now) {
+            return Ok(timer);
+        }
+        // a timer that is back to how every unknown timer looks needs no entry
+        if timer.status == TimerStatus::Stopped && timer.accumulated.is_zero() && timer.target.is_none() {
+            timers.remove(name);
+        } else {
+            timers.insert(name.to_string(), TimerEntry { timer: timer.clone(), changed_at: now });
+        }
+        // sent under the lock, so subscribers see changes in the order they were made; no subscribers is fine
+        let _ = self.changes.send((name.to_string(), timer.clone()));
+        Ok(timer)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.timers.lock().expect("timers poisoned").len()
+    }
+
+    /// Receive every change to every timer from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, Timer)> {
+        self.changes.subscribe()
+    }
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Self::new(DEFAULT_EXPIRY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.ymd(2022, 1, 1).and_hms(0, 0, 0) + Duration::seconds(secs)
+    }
+
+    fn apply(timer: &mut Timer, operation: TimerOperation, secs: i64) -> bool {
+        timer.apply(operation, None, at(secs))
+    }
+
+    #[test]
+    fn start_pause_resume() {
+        let mut timer = Timer::default();
+        assert!(apply(&mut timer, TimerOperation::Start, 0));
+        assert!(timer.status == TimerStatus::Running { since: at(0) });
+        // the first start wins
+        assert!(!apply(&mut timer, TimerOperation::Start, 5));
+        assert_eq!(timer.elapsed(at(10)), Duration::seconds(10));
+
+        assert!(apply(&mut timer, TimerOperation::Pause, 10));
+        assert!(!apply(&mut timer, TimerOperation::Pause, 15));
+        assert!(timer.status == TimerStatus::Paused);
+        assert_eq!(timer.elapsed(at(100)), Duration::seconds(10));
+
+        assert!(apply(&mut timer, TimerOperation::Resume, 100));
+        assert!(!apply(&mut timer, TimerOperation::Resume, 101));
+        assert_eq!(timer.elapsed(at(105)), Duration::seconds(15));
+    }
+
+    #[test]
+    fn operations_that_do_not_apply_change_nothing() {
+        let mut timer = Timer::default();
+        assert!(!apply(&mut timer, TimerOperation::Pause, 0));
+        assert!(!apply(&mut timer, TimerOperation::Resume, 0));
+        assert!(!apply(&mut timer, TimerOperation::Target, 0));
+        assert!(timer.status == TimerStatus::Stopped);
+    }
+
+    #[test]
+    fn reset_and_restart() {
+        let mut timer = Timer::default();
+        timer.apply(TimerOperation::Start, Some(TargetChange::Duration(Duration::seconds(60))), at(0));
+        assert!(apply(&mut timer, TimerOperation::Reset, 20));
+        assert!(timer.status == TimerStatus::Stopped);
+        assert_eq!(timer.elapsed(at(30)), Duration::zero());
+        assert_eq!(timer.target, Some(Duration::seconds(60)));
+        // a stopped timer at zero is already reset
+        assert!(!apply(&mut timer, TimerOperation::Reset, 30));
+
+        apply(&mut timer, TimerOperation::Start, 30);
+        apply(&mut timer, TimerOperation::Pause, 40);
+        assert!(apply(&mut timer, TimerOperation::Restart, 50));
+        assert!(timer.status == TimerStatus::Running { since: at(50) });
+        assert_eq!(timer.elapsed(at(55)), Duration::seconds(5));
+    }
+
+    #[test]
+    fn targets_only_come_with_changes() {
+        let mut timer = Timer::default();
+        let target = Some(TargetChange::Duration(Duration::seconds(60)));
+        assert!(timer.apply(TimerOperation::Start, target, at(0)));
+        assert_eq!(timer.remaining(at(15)), Some(Duration::seconds(45)));
+        assert_eq!(timer.ends_at(), Some(at(60)));
+        assert_eq!(timer.remaining(at(90)), Some(Duration::zero()));
+
+        // a start that loses to an earlier one does not replace the target
+        assert!(!timer.apply(TimerOperation::Start, Some(TargetChange::Duration(Duration::seconds(5))), at(20)));
+        assert_eq!(timer.target, Some(Duration::seconds(60)));
+
+        assert!(timer.apply(TimerOperation::Target, Some(TargetChange::Clear), at(20)));
+        assert_eq!(timer.target, None);
+        assert_eq!(timer.ends_at(), None);
+        assert!(!timer.apply(TimerOperation::Target, Some(TargetChange::Clear), at(25)));
+    }
+
+    #[test]
+    fn end_times() {
+        let mut timer = Timer::default();
+        apply(&mut timer, TimerOperation::Start, 0);
+        assert!(timer.apply(TimerOperation::Target, Some(TargetChange::EndsAt(at(15))), at(10)));
+        assert_eq!(timer.target, Some(Duration::seconds(15)));
+        assert_eq!(timer.ends_at(), Some(at(15)));
+
+        // an end time in the past finishes the timer now rather than giving it a negative target
+        assert!(timer.apply(TimerOperation::Target, Some(TargetChange::EndsAt(at(-100))), at(20)));
+        assert_eq!(timer.target, Some(Duration::seconds(20)));
+        assert_eq!(timer.remaining(at(20)), Some(Duration::zero()));
+
+        let mut stopped = Timer::default();
+        stopped.apply(TimerOperation::Target, Some(TargetChange::EndsAt(at(-100))), at(0));
+        assert_eq!(stopped.target, Some(Duration::zero()));
+    }
+
+    #[test]
+    fn changes_are_broadcast() {
+        let timers = Timers::default();
+        let mut changes = timers.subscribe();
+        timers.apply("race", TimerOperation::Start, None).unwrap();
+        timers.apply("race", TimerOperation::Start, None).unwrap();
+        timers.apply("race", TimerOperation::Pause, None).unwrap();
+        let (name, timer) = changes.try_recv().unwrap();
+        assert_eq!(name, "race");
+        assert_eq!(timer.status_name(), "running");
+        assert_eq!(changes.try_recv().unwrap().1.status_name(), "paused");
+        assert!(changes.try_recv().is_err());
+
+        assert_eq!(timers.get("never-used").unwrap().status_name(), "stopped");
+        assert!(timers.apply("", TimerOperation::Start, None).is_err());
+    }
+
+    #[test]
+    fn only_changed_timers_are_kept() {
+        let timers = Timers::default();
+        timers.apply("never-started", TimerOperation::Pause, None).unwrap();
+        timers.apply("never-started", TimerOperation::Resume, None).unwrap();
+        timers.get("never-started").unwrap();
+        assert_eq!(timers.len(), 0);
+
+        timers.apply("race", TimerOperation::Start, None).unwrap();
+        assert_eq!(timers.len(), 1);
+        // resetting a timer without a target forgets it
+        timers.apply("race", TimerOperation::Reset, None).unwrap();
+        assert_eq!(timers.len(), 0);
+    }
+
+    #[test]
+    fn idle_timers_expire() {
+        let timers = Timers::new(StdDuration::from_secs(60));
+        timers.apply_at("stopwatch", TimerOperation::Start, None, at(0)).unwrap();
+        timers.apply_at("countdown", TimerOperation::Start, Some(TargetChange::Duration(Duration::seconds(120))), at(0)).unwrap();
+        assert_eq!(timers.apply_at("stopwatch", TimerOperation::Get, None, at(60)).unwrap().status_name(), "running");
+
+        // a countdown is kept until it has been finished for as long as the expiry
+        assert_eq!(timers.apply_at("stopwatch", TimerOperation::Get, None, at(61)).unwrap().status_name(), "stopped");
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers.apply_at("countdown", TimerOperation::Get, None, at(180)).unwrap().status_name(), "running");
+        assert_eq!(timers.apply_at("countdown", TimerOperation::Get, None, at(181)).unwrap().status_name(), "stopped");
+        assert_eq!(timers.len(), 0);
+    }
+}
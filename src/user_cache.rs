@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use chrono::{Datelike, DateTime, Duration, Utc};
+use futures::StreamExt;
+
+use crate::client::NeosApi;
+use crate::dto::user_dto::AbridgedUser;
+use crate::error::Error;
+use crate::json_file::JsonFile;
+use crate::single_flight::SingleFlight;
+
+lazy_static! {
+    /// normal user cache expiry time
+    static ref CACHE_EXPIRY_TIME: Duration = Duration::days(30);
+    /// edge case user cache expiry time for when we're at the Patreon renewal time of the month
+    static ref CACHE_EXPIRY_TIME_EDGE_CASE: Duration = Duration::hours(6);
+}
+
+/// how many cache misses [`UserCache::lookup_many`] resolves against the Neos API at once
+pub const MAX_CONCURRENT_LOOKUPS: usize = 32;
+
+/// Disk-backed cache of abridged user data, keyed by user ID.
+///
+/// The map lock is never held across an await point, so cache hits are never blocked by upstream requests. Misses are
+/// coalesced per user ID, and the cache file is written outside of the map lock.
+pub struct UserCache {
+    cache_file: JsonFile,
+    users: RwLock<HashMap<String, AbridgedUser>>,
+    lookups: SingleFlight<String, Result<AbridgedUser, Arc<Error>>>,
+}
+
+impl UserCache {
+    /// Load the cache from disk. A missing or unreadable cache is not a serious problem, so this falls back to an empty
+    /// cache.
+    pub fn load(cache_file: PathBuf) -> Self {
+        let (cache_file, users) = JsonFile::load("user cache", cache_file.clone()).unwrap_or_else(|e| {
+            eprintln!("Failed to load cache from disk; defaulting to empty. This is not a serious problem: {}", e);
+            (JsonFile::new("user cache", cache_file), HashMap::new())
+        });
+
+        UserCache {
+            cache_file,
+            users: RwLock::new(users),
+            lookups: SingleFlight::new(),
+        }
+    }
+
+    /// Look up a user, only hitting the Neos API if the cached entry is missing or expired. Concurrent misses for the
+    /// same user share one upstream request.
+    pub async fn lookup(&self, neos_api: &dyn NeosApi, user_id: String) -> Result<AbridgedUser, Error> {
+        match self.get(&user_id) {
+            Some(user) => {
+                if is_cache_time_valid(&user.cache_time) {
+                    return Ok(user);
+                } else {
+                    println!("caching expired user {}", user_id);
+                }
+            }
+            None => {
+                println!("caching new user {}", user_id);
+            }
+        };
+
+        // hit real service
+        let user = self.lookups.run(user_id.clone(), || async {
+            let user: AbridgedUser = neos_api.get_user(&user_id).await.map_err(Arc::new)?.abridge(Utc::now());
+            self.users.write().expect("user cache poisoned").insert(user_id.clone(), user.clone());
+            self.cache_file.mark_dirty();
+            Ok(user)
+        }).await.map_err(Error::from)?;
+
+        self.save().await;
+        Ok(user)
+    }
+
+    /// Look up several users at once, with at most [`MAX_CONCURRENT_LOOKUPS`] upstream requests in flight. Duplicate
+    /// IDs are only looked up once.
+    pub async fn lookup_many<I>(&self, neos_api: &dyn NeosApi, user_ids: I) -> HashMap<String, Result<AbridgedUser, Error>>
+        where I: IntoIterator<Item=String>
+    {
+        let mut user_ids = user_ids.into_iter().collect::<Vec<String>>();
+        user_ids.sort_unstable();
+        user_ids.dedup();
+
+        futures::stream::iter(user_ids)
+            .map(|user_id| async move {
+                let user = self.lookup(neos_api, user_id.clone()).await;
+                (user_id, user)
+            })
+            .buffer_unordered(MAX_CONCURRENT_LOOKUPS)
+            .collect()
+            .await
+    }
+
+    fn get(&self, user_id: &str) -> Option<AbridgedUser> {
+        self.users.read().expect("user cache poisoned").get(user_id).cloned()
+    }
+
+    /// Persist the cache if it has unsaved changes
+    async fn save(&self) {
+        self.cache_file.save(|| serde_json::to_string(&*self.users.read().expect("user cache poisoned"))).await
+    }
+}
+
+/// check a cache entry's creation time to see if it is valid or expired
+fn is_cache_time_valid(cache_time: &DateTime<Utc>) -> bool {
+    let now = Utc::now();
+    let cache_entry_age: Duration = now.signed_duration_since(*cache_time);
+    if cache_entry_age > *CACHE_EXPIRY_TIME {
+        // normal cache expiry
+        false
+    } else if cache_entry_age > *CACHE_EXPIRY_TIME_EDGE_CASE && now.date().day() <= 4 {
+        // patreon renewal edge case
+        false
+    } else {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::client::fake::{self, FakeNeosApi};
+    use crate::json_file::TempFile;
+
+    #[tokio::test]
+    async fn lookups_are_cached_on_disk() {
+        let temp_file = TempFile::new("user-cache-on-disk");
+        let registered = Utc.ymd(2020, 10, 13).and_hms(19, 41, 20);
+        let neos_api = FakeNeosApi::new(vec![], vec![fake::user("U-zkxs", registered)]);
+        let user_cache = UserCache::load(temp_file.path());
+        assert_eq!(user_cache.lookup(&neos_api, "U-zkxs".to_string()).await.unwrap().registration_date, registered);
+        assert!(matches!(user_cache.lookup(&neos_api, "U-nobody".to_string()).await, Err(Error::UserNotFound(_))));
+
+        // later lookups, even from a reloaded cache, do not need the API
+        neos_api.users.lock().unwrap().clear();
+        assert!(user_cache.lookup(&neos_api, "U-zkxs".to_string()).await.is_ok());
+        let reloaded = UserCache::load(temp_file.path());
+        assert_eq!(reloaded.lookup(&neos_api, "U-zkxs".to_string()).await.unwrap().registration_date, registered);
+    }
+
+    #[tokio::test]
+    async fn an_unreadable_cache_starts_empty() {
+        let temp_file = TempFile::new("user-cache-unreadable");
+        std::fs::write(temp_file.path(), "{\"U-zkxs\":").unwrap();
+        let neos_api = FakeNeosApi::new(vec![], vec![fake::user("U-zkxs", Utc::now())]);
+        let user_cache = UserCache::load(temp_file.path());
+        assert!(user_cache.lookup(&neos_api, "U-zkxs".to_string()).await.is_ok());
+        // and is replaced on the next save
+        assert!(UserCache::load(temp_file.path()).get("U-zkxs").is_some());
+    }
+}